@@ -0,0 +1,46 @@
+//! Compares the seek-based reader with the memory-mapped one on a scan over all the tiles.
+//!
+//! The archive defaults to the small test fixture; set `PMTILES_BENCH_FILE` to benchmark a
+//! real one (e.g. the GSI optimal_bvmap archive).
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use dump_pmtiles_labels::{PMTilesFile, RangeReader};
+
+/// `open` is called twice, for reading the directories and the tiles respectively, as
+/// iterating over the entries borrows the file. The tiles are read with `parse_tile`, which
+/// decodes uncompressed tiles without copying them out of the memory map.
+fn scan<R: RangeReader>(open: impl Fn() -> PMTilesFile<R>) -> usize {
+    let mut n_strings = 0;
+
+    let mut directories = open();
+    let mut file = open();
+    for e in directories.tile_entries().unwrap() {
+        let e = e.unwrap();
+        let tile = file.parse_tile(e.offset, e.length as usize).unwrap();
+        for l in tile.layers {
+            n_strings += l.values.iter().filter(|v| v.string_value.is_some()).count();
+        }
+    }
+
+    n_strings
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let path = std::env::var("PMTILES_BENCH_FILE")
+        .unwrap_or_else(|_| "src/test/test_fixture_1.pmtiles".to_string());
+
+    let mut group = c.benchmark_group("scan");
+    group.sample_size(10);
+
+    group.bench_function("seek", |b| {
+        b.iter(|| scan(|| PMTilesFile::new(&path).unwrap()))
+    });
+    group.bench_function("mmap", |b| {
+        b.iter(|| scan(|| PMTilesFile::open_mmap(&path).unwrap()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
@@ -0,0 +1,166 @@
+//! Geographic bounding boxes and the tiles intersecting them.
+
+use std::{f64::consts::PI, ops::RangeInclusive, str::FromStr};
+
+use crate::{header::PMTilesHeaderV3, tile_id};
+
+/// The latitude limit of Web Mercator, where the map becomes square.
+const MAX_LAT: f64 = 85.051_128_779_806_59;
+
+/// A box of WGS84 longitudes and latitudes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BBox {
+    /// The bounds of the archive in the header. Returns `None` if they are empty, as some
+    /// writers leave them zero.
+    pub fn from_header(header: &PMTilesHeaderV3) -> Option<Self> {
+        let bbox = Self {
+            min_lon: header.min_position.lon as f64,
+            min_lat: header.min_position.lat as f64,
+            max_lon: header.max_position.lon as f64,
+            max_lat: header.max_position.lat as f64,
+        };
+        (bbox.min_lon < bbox.max_lon && bbox.min_lat < bbox.max_lat).then_some(bbox)
+    }
+
+    /// Returns the overlap of the boxes, or `None` if they don't intersect.
+    pub fn intersection(&self, other: &BBox) -> Option<Self> {
+        let bbox = Self {
+            min_lon: self.min_lon.max(other.min_lon),
+            min_lat: self.min_lat.max(other.min_lat),
+            max_lon: self.max_lon.min(other.max_lon),
+            max_lat: self.max_lat.min(other.max_lat),
+        };
+        (bbox.min_lon <= bbox.max_lon && bbox.min_lat <= bbox.max_lat).then_some(bbox)
+    }
+
+    /// Clips the box to the bounds of the archive, if the header has them. Returns `None` if
+    /// the archive has no tiles in the box.
+    pub fn clip_to_header(&self, header: &PMTilesHeaderV3) -> Option<Self> {
+        match Self::from_header(header) {
+            Some(bounds) => self.intersection(&bounds),
+            None => Some(*self),
+        }
+    }
+
+    /// Returns the x and y of the tiles intersecting the box at the zoom level. Tiles touching
+    /// the box on an edge are included.
+    pub fn tile_range(&self, z: u8) -> (RangeInclusive<u32>, RangeInclusive<u32>) {
+        (
+            lon_to_x(self.min_lon, z)..=lon_to_x(self.max_lon, z),
+            // y grows southward
+            lat_to_y(self.max_lat, z)..=lat_to_y(self.min_lat, z),
+        )
+    }
+
+    pub fn intersects_tile(&self, z: u8, x: u32, y: u32) -> bool {
+        let (xs, ys) = self.tile_range(z);
+        xs.contains(&x) && ys.contains(&y)
+    }
+
+    pub fn intersects_tile_id(&self, tile_id: u64) -> bool {
+        tile_id::tile_id_to_zxy(tile_id).is_some_and(|(z, x, y)| self.intersects_tile(z, x, y))
+    }
+}
+
+/// Parses `minlon,minlat,maxlon,maxlat`.
+impl FromStr for BBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid bbox {s:?}: {e}"))?;
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err(format!(
+                "invalid bbox {s:?}: expected minlon,minlat,maxlon,maxlat"
+            ));
+        };
+
+        let lon_range = -180.0..=180.0;
+        let lat_range = -90.0..=90.0;
+        if !(lon_range.contains(&min_lon)
+            && lon_range.contains(&max_lon)
+            && lat_range.contains(&min_lat)
+            && lat_range.contains(&max_lat))
+        {
+            return Err(format!("invalid bbox {s:?}: out of range"));
+        }
+        if min_lon > max_lon || min_lat > max_lat {
+            return Err(format!("invalid bbox {s:?}: min is greater than max"));
+        }
+
+        Ok(Self {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        })
+    }
+}
+
+fn lon_to_x(lon: f64, z: u8) -> u32 {
+    let n = (1u64 << z) as f64;
+    ((lon + 180.0) / 360.0 * n).floor().clamp(0.0, n - 1.0) as u32
+}
+
+fn lat_to_y(lat: f64, z: u8) -> u32 {
+    let n = (1u64 << z) as f64;
+    let lat = lat.clamp(-MAX_LAT, MAX_LAT).to_radians();
+    let y = (1.0 - lat.tan().asinh() / PI) / 2.0 * n;
+    y.floor().clamp(0.0, n - 1.0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bbox() {
+        assert_eq!(
+            "139.5,35.5,140,36".parse::<BBox>(),
+            Ok(BBox {
+                min_lon: 139.5,
+                min_lat: 35.5,
+                max_lon: 140.0,
+                max_lat: 36.0,
+            })
+        );
+        assert!("139.5,35.5,140".parse::<BBox>().is_err());
+        assert!("139.5,35.5,140,x".parse::<BBox>().is_err());
+        assert!("140,35.5,139.5,36".parse::<BBox>().is_err());
+        assert!("139.5,35.5,140,91".parse::<BBox>().is_err());
+    }
+
+    #[test]
+    fn test_tile_range() {
+        let world: BBox = "-180,-90,180,90".parse().unwrap();
+        assert_eq!(world.tile_range(0), (0..=0, 0..=0));
+        assert_eq!(world.tile_range(2), (0..=3, 0..=3));
+
+        let tokyo: BBox = "139.7,35.68,139.7,35.68".parse().unwrap();
+        assert_eq!(tokyo.tile_range(12), (3637..=3637, 1612..=1612));
+        assert!(tokyo.intersects_tile(12, 3637, 1612));
+        assert!(!tokyo.intersects_tile(12, 3638, 1612));
+
+        let bbox: BBox = "139.5,35.5,140,36".parse().unwrap();
+        assert_eq!(bbox.tile_range(12), (3635..=3640, 1608..=1615));
+    }
+
+    #[test]
+    fn test_intersection() {
+        let a: BBox = "0,0,10,10".parse().unwrap();
+        let b: BBox = "5,-5,15,5".parse().unwrap();
+        assert_eq!(a.intersection(&b), Some("5,0,10,5".parse().unwrap()));
+        let c: BBox = "11,0,12,10".parse().unwrap();
+        assert_eq!(a.intersection(&c), None);
+    }
+}
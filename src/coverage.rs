@@ -0,0 +1,90 @@
+//! Finding the characters of the labels that a font doesn't cover.
+
+use std::collections::HashMap;
+
+use crate::scan::{count_chars, sort_counts};
+
+/// A character without a glyph in the font.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingChar {
+    pub c: char,
+    pub count: usize,
+    /// The most frequent strings containing the character
+    pub samples: Vec<String>,
+}
+
+/// Finds the characters in the counted strings that `has_glyph` returns false for, in
+/// descending order of their counts, with up to `n_samples` strings containing each.
+///
+/// Control characters are ignored, as they are not rendered.
+pub fn find_missing_chars(
+    strings: &HashMap<String, usize>,
+    has_glyph: impl Fn(char) -> bool,
+    n_samples: usize,
+) -> Vec<MissingChar> {
+    let mut missing: Vec<MissingChar> = sort_counts(count_chars(strings))
+        .into_iter()
+        .filter(|&(c, _)| !c.is_control() && !has_glyph(c))
+        .map(|(c, count)| MissingChar {
+            c,
+            count,
+            samples: vec![],
+        })
+        .collect();
+
+    let index: HashMap<char, usize> = missing.iter().enumerate().map(|(i, m)| (m.c, i)).collect();
+    let mut sorted_strings: Vec<(&String, usize)> =
+        strings.iter().map(|(s, &count)| (s, count)).collect();
+    sorted_strings.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+    for (s, _) in sorted_strings {
+        for c in s.chars() {
+            let Some(&i) = index.get(&c) else {
+                continue;
+            };
+            let samples = &mut missing[i].samples;
+            // a string may contain the character more than once
+            if samples.len() < n_samples && samples.last() != Some(s) {
+                samples.push(s.clone());
+            }
+        }
+    }
+
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_missing_chars() {
+        let strings = HashMap::from([
+            ("東京".to_string(), 10),
+            ("𠮟る".to_string(), 1),
+            ("髙島屋".to_string(), 3),
+            ("髙島髙".to_string(), 2),
+            ("髙\n".to_string(), 1),
+        ]);
+        let font = ['東', '京', 'る', '島', '屋'];
+
+        let missing = find_missing_chars(&strings, |c| font.contains(&c), 2);
+        assert_eq!(
+            missing,
+            vec![
+                MissingChar {
+                    c: '髙',
+                    count: 8,
+                    samples: vec!["髙島屋".to_string(), "髙島髙".to_string()],
+                },
+                MissingChar {
+                    c: '𠮟',
+                    count: 1,
+                    samples: vec!["𠮟る".to_string()],
+                },
+            ]
+        );
+
+        assert!(find_missing_chars(&strings, |_| true, 2).is_empty());
+    }
+}
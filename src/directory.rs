@@ -1,8 +1,19 @@
+use std::collections::{HashMap, VecDeque};
+
 use nom::{IResult, Parser, multi::count};
 
+use crate::util;
 use crate::varint::parse_varint;
 
-#[derive(Debug, PartialEq)]
+/// Leaf directories nested deeper than this are considered corrupt (e.g. a leaf directory
+/// pointing to itself). Real archives have only a few levels.
+pub(crate) const MAX_DEPTH: usize = 16;
+
+/// Directories expanding to more entries than this are considered corrupt, so that a broken
+/// run length doesn't exhaust memory. Real directories have far fewer tiles.
+const MAX_EXPANDED_ENTRIES: u64 = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct PMTilesEntry {
     pub tile_id: u64,
     pub offset: u64,
@@ -10,7 +21,9 @@ pub struct PMTilesEntry {
     pub is_tile: bool,
 }
 
-pub(crate) fn parse_directory(input: &[u8]) -> IResult<&[u8], Vec<PMTilesEntry>> {
+/// Parses a decompressed directory. Run-length encoded entries are expanded into one entry
+/// per tile.
+pub fn parse_directory(input: &[u8]) -> IResult<&[u8], Vec<PMTilesEntry>> {
     let (input, entry_count) = parse_varint(input)?;
     let entry_count = entry_count as usize;
     let (input, tile_ids) = count(parse_varint, entry_count).parse(input)?;
@@ -18,19 +31,39 @@ pub(crate) fn parse_directory(input: &[u8]) -> IResult<&[u8], Vec<PMTilesEntry>>
     let (input, lengths) = count(parse_varint, entry_count).parse(input)?;
     let (input, offsets) = count(parse_varint, entry_count).parse(input)?;
 
-    let mut result = vec![];
-    let mut last_tile_id = 0;
-    let mut last_offset = 0;
+    // check the run lengths before allocating for them
+    let mut n_expanded: u64 = 0;
+    for &run_length in &run_lengths {
+        n_expanded = n_expanded.saturating_add(run_length.max(1));
+    }
+    if n_expanded > MAX_EXPANDED_ENTRIES {
+        return util::nom_error(input);
+    }
+
+    let mut result = Vec::with_capacity(n_expanded as usize);
+    let mut last_tile_id: u64 = 0;
+    let mut last_offset: u64 = 0;
 
     for i in 0..entry_count {
-        last_tile_id += tile_ids[i];
         let run_length = run_lengths[i];
         let length = lengths[i];
-        last_offset = if offsets[i] == 0 && i > 0 {
-            last_offset + lengths[i - 1]
-        } else {
-            offsets[i] - 1
+
+        // the values are not trustworthy if the directory is corrupt
+        let (Some(tile_id), Some(offset)) = (
+            last_tile_id.checked_add(tile_ids[i]),
+            match (offsets[i], i) {
+                (0, 0) => None,
+                (0, _) => last_offset.checked_add(lengths[i - 1]),
+                (o, _) => Some(o - 1),
+            },
+        ) else {
+            return util::nom_error(input);
         };
+        if tile_id.checked_add(run_length).is_none() {
+            return util::nom_error(input);
+        }
+        last_tile_id = tile_id;
+        last_offset = offset;
 
         if run_length == 0 {
             result.push(PMTilesEntry {
@@ -54,6 +87,41 @@ pub(crate) fn parse_directory(input: &[u8]) -> IResult<&[u8], Vec<PMTilesEntry>>
     Ok((input, result))
 }
 
+/// Parsed directories keyed by their offset in the archive. When full, the oldest directory is
+/// evicted.
+pub(crate) struct DirectoryCache {
+    capacity: usize,
+    directories: HashMap<u64, Vec<PMTilesEntry>>,
+    order: VecDeque<u64>,
+}
+
+impl DirectoryCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            directories: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, offset: u64) -> Option<&Vec<PMTilesEntry>> {
+        self.directories.get(&offset)
+    }
+
+    pub(crate) fn insert(&mut self, offset: u64, entries: Vec<PMTilesEntry>) {
+        if self.capacity == 0 || self.directories.contains_key(&offset) {
+            return;
+        }
+        if self.order.len() == self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.directories.remove(&oldest);
+        }
+        self.order.push_back(offset);
+        self.directories.insert(offset, entries);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use flate2::read::GzDecoder;
@@ -77,4 +145,48 @@ mod tests {
         assert_eq!(result[0].offset, 0);
         assert_eq!(result[0].length, 69);
     }
+
+    #[test]
+    fn test_parse_corrupt_directory() {
+        // 2 entries, but only 1 tile ID
+        assert!(parse_directory(&[2, 0]).is_err());
+
+        // the offset of the first entry cannot be omitted
+        assert!(parse_directory(&[1, 0, 1, 10, 0]).is_err());
+
+        // a tile_id overflowing u64
+        let mut data = vec![2];
+        data.extend([0xff; 9]);
+        data.extend([0x01, 0xff]);
+        data.extend([0xff; 8]);
+        data.extend([0x01, 1, 1, 1, 1, 1, 1]);
+        assert!(parse_directory(&data).is_err());
+
+        // a run length of 1 << 36, which would take terabytes to expand
+        let mut data = vec![1, 0];
+        data.extend([0x80, 0x80, 0x80, 0x80, 0x80, 0x02]);
+        data.extend([1, 1]);
+        assert!(parse_directory(&data).is_err());
+    }
+
+    #[test]
+    fn test_directory_cache() {
+        let entry = |tile_id| PMTilesEntry {
+            tile_id,
+            offset: 0,
+            length: 1,
+            is_tile: true,
+        };
+
+        let mut cache = DirectoryCache::new(2);
+        cache.insert(10, vec![entry(1)]);
+        cache.insert(20, vec![entry(2)]);
+        assert_eq!(cache.get(10), Some(&vec![entry(1)]));
+
+        // evicts the oldest one
+        cache.insert(30, vec![entry(3)]);
+        assert_eq!(cache.get(10), None);
+        assert_eq!(cache.get(20), Some(&vec![entry(2)]));
+        assert_eq!(cache.get(30), Some(&vec![entry(3)]));
+    }
 }
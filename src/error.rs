@@ -0,0 +1,79 @@
+use std::fmt;
+
+/// Errors that can occur while reading a PMTiles archive.
+///
+/// Offsets are byte offsets from the beginning of the archive.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from the archive failed, including when it ends unexpectedly.
+    Io { offset: u64, source: std::io::Error },
+    /// The archive doesn't start with the `PMTiles` magic number.
+    BadMagic,
+    /// The spec version is not 3.
+    UnsupportedVersion(u8),
+    /// A header byte holds a value not defined in the spec (e.g. compression type 5).
+    InvalidHeaderValue { offset: u64, value: u8 },
+    /// The directory at the offset has broken varints or inconsistent entries.
+    CorruptDirectory { offset: u64 },
+    /// The section at the offset is compressed with an unknown compression type.
+    UnsupportedCompression { offset: u64 },
+    /// Decompressing the section at the offset failed.
+    Decompression { offset: u64, source: std::io::Error },
+    /// The tile at the offset is not a valid Mapbox Vector Tile.
+    Decode {
+        offset: u64,
+        source: prost::DecodeError,
+    },
+    /// The metadata at the offset is not valid UTF-8.
+    InvalidMetadata {
+        offset: u64,
+        source: std::string::FromUtf8Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { offset, source } => {
+                write!(f, "failed to read at offset {offset}: {source}")
+            }
+            Error::BadMagic => write!(f, "not a PMTiles archive"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported PMTiles version: {version}")
+            }
+            Error::InvalidHeaderValue { offset, value } => {
+                write!(f, "invalid header value {value} at offset {offset}")
+            }
+            Error::CorruptDirectory { offset } => {
+                write!(f, "corrupt directory at offset {offset}")
+            }
+            Error::UnsupportedCompression { offset } => {
+                write!(f, "unknown compression of the section at offset {offset}")
+            }
+            Error::Decompression { offset, source } => {
+                write!(
+                    f,
+                    "failed to decompress the section at offset {offset}: {source}"
+                )
+            }
+            Error::Decode { offset, source } => {
+                write!(f, "failed to decode the tile at offset {offset}: {source}")
+            }
+            Error::InvalidMetadata { offset, source } => {
+                write!(f, "invalid metadata at offset {offset}: {source}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decompression { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            Error::InvalidMetadata { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
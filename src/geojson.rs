@@ -0,0 +1,175 @@
+//! Converting vector tile features to GeoJSON.
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    geometry::{Geometry, GeometryError, decode_geometry},
+    mvt,
+};
+
+/// Reconstructs the properties of the feature from the keys and the values of the layer, which
+/// the tags of the feature refer to by index. Broken indices are skipped.
+pub fn properties(layer: &mvt::tile::Layer, feature: &mvt::tile::Feature) -> Map<String, Value> {
+    feature
+        .tags
+        .chunks_exact(2)
+        .filter_map(|kv| {
+            let key = layer.keys.get(kv[0] as usize)?;
+            let value = layer.values.get(kv[1] as usize)?;
+            Some((key.clone(), value_to_json(value)))
+        })
+        .collect()
+}
+
+/// Converts a property value. Values without any field set become `null`.
+pub fn value_to_json(value: &mvt::tile::Value) -> Value {
+    if let Some(v) = &value.string_value {
+        json!(v)
+    } else if let Some(v) = value.float_value {
+        json!(v)
+    } else if let Some(v) = value.double_value {
+        json!(v)
+    } else if let Some(v) = value.int_value {
+        json!(v)
+    } else if let Some(v) = value.uint_value {
+        json!(v)
+    } else if let Some(v) = value.sint_value {
+        json!(v)
+    } else if let Some(v) = value.bool_value {
+        json!(v)
+    } else {
+        Value::Null
+    }
+}
+
+/// Converts a geometry in WGS84 to a GeoJSON geometry. Geometries with multiple parts become
+/// `Multi*` geometries.
+///
+/// Rings are reversed so that exterior rings are counterclockwise and interior rings are
+/// clockwise, as RFC 7946 requires, whereas vector tiles wind them the other way round.
+pub fn geometry_to_json(geometry: &Geometry<(f64, f64)>) -> Value {
+    let line = |points: &Vec<(f64, f64)>| -> Vec<[f64; 2]> {
+        points.iter().map(|&(lon, lat)| [lon, lat]).collect()
+    };
+
+    match geometry {
+        Geometry::Points(points) => match &points[..] {
+            [(lon, lat)] => json!({ "type": "Point", "coordinates": [lon, lat] }),
+            _ => json!({ "type": "MultiPoint", "coordinates": line(points) }),
+        },
+        Geometry::LineStrings(lines) => match &lines[..] {
+            [points] => json!({ "type": "LineString", "coordinates": line(points) }),
+            _ => json!({
+                "type": "MultiLineString",
+                "coordinates": lines.iter().map(line).collect::<Vec<_>>(),
+            }),
+        },
+        Geometry::Polygons(polygons) => {
+            let rings: Vec<Vec<Vec<[f64; 2]>>> = polygons
+                .iter()
+                .map(|p| {
+                    std::iter::once(&p.exterior)
+                        .chain(&p.interiors)
+                        .map(|ring| ring.iter().rev().map(|&(lon, lat)| [lon, lat]).collect())
+                        .collect()
+                })
+                .collect();
+            match &rings[..] {
+                [rings] => json!({ "type": "Polygon", "coordinates": rings }),
+                _ => json!({ "type": "MultiPolygon", "coordinates": rings }),
+            }
+        }
+    }
+}
+
+/// Converts a feature of the layer in the tile z/x/y to a GeoJSON feature. The layer name and
+/// the tile are kept in the foreign members `layer` and `tile`.
+pub fn feature_to_json(
+    layer: &mvt::tile::Layer,
+    feature: &mvt::tile::Feature,
+    (z, x, y): (u8, u32, u32),
+) -> Result<Value, GeometryError> {
+    let geometry = decode_geometry(feature)?.to_wgs84(z, x, y, layer.extent());
+
+    let mut result = json!({
+        "type": "Feature",
+        "geometry": geometry_to_json(&geometry),
+        "properties": properties(layer, feature),
+        "layer": layer.name,
+        "tile": format!("{z}/{x}/{y}"),
+    });
+    if let Some(id) = feature.id {
+        result["id"] = json!(id);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geometry::Polygon, test_util::make_tile};
+
+    #[test]
+    fn test_feature_to_json() {
+        let tile = make_tile(&[("Anno", &[&[("vt_text", "東京"), ("vt_code", "LC")]])]);
+        let layer = &tile.layers[0];
+
+        let feature = feature_to_json(layer, &layer.features[0], (0, 0, 0)).unwrap();
+        assert_eq!(feature["type"], "Feature");
+        assert_eq!(feature["geometry"]["type"], "Point");
+        assert_eq!(
+            feature["properties"],
+            json!({ "vt_text": "東京", "vt_code": "LC" })
+        );
+        assert_eq!(feature["layer"], "Anno");
+        assert_eq!(feature["tile"], "0/0/0");
+        assert!(feature.get("id").is_none());
+    }
+
+    #[test]
+    fn test_value_to_json() {
+        let value = |f: fn(&mut mvt::tile::Value)| {
+            let mut v = mvt::tile::Value::default();
+            f(&mut v);
+            value_to_json(&v)
+        };
+        assert_eq!(value(|v| v.int_value = Some(-3)), json!(-3));
+        assert_eq!(value(|v| v.uint_value = Some(3)), json!(3));
+        assert_eq!(value(|v| v.double_value = Some(1.5)), json!(1.5));
+        assert_eq!(value(|v| v.bool_value = Some(true)), json!(true));
+        assert_eq!(value(|_| {}), Value::Null);
+    }
+
+    #[test]
+    fn test_geometry_to_json() {
+        assert_eq!(
+            geometry_to_json(&Geometry::Points(vec![(1.0, 2.0), (3.0, 4.0)])),
+            json!({ "type": "MultiPoint", "coordinates": [[1.0, 2.0], [3.0, 4.0]] })
+        );
+        assert_eq!(
+            geometry_to_json(&Geometry::LineStrings(vec![vec![(1.0, 2.0), (3.0, 4.0)]])),
+            json!({ "type": "LineString", "coordinates": [[1.0, 2.0], [3.0, 4.0]] })
+        );
+
+        // clockwise on the map, as decoded from vector tiles
+        let square = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.0, 0.0)];
+        let polygon = Polygon {
+            exterior: square.clone(),
+            interiors: vec![square.clone()],
+        };
+        assert_eq!(
+            geometry_to_json(&Geometry::Polygons(vec![polygon.clone()])),
+            json!({
+                "type": "Polygon",
+                "coordinates": [
+                    [[0.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]],
+                    [[0.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]],
+                ],
+            })
+        );
+        let multi = geometry_to_json(&Geometry::Polygons(vec![polygon.clone(), polygon]));
+        assert_eq!(multi["type"], "MultiPolygon");
+        assert_eq!(multi["coordinates"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,348 @@
+//! Decoding the command-encoded geometries of vector tile features.
+//!
+//! cf. https://github.com/mapbox/vector-tile-spec/tree/master/2.1#43-geometry-encoding
+
+use std::{f64::consts::PI, fmt};
+
+use crate::mvt::tile::{Feature, GeomType};
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+/// The geometry of a feature. Each variant may have multiple parts, e.g. a multi-point.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry<T> {
+    Points(Vec<T>),
+    LineStrings(Vec<Vec<T>>),
+    Polygons(Vec<Polygon<T>>),
+}
+
+/// A polygon whose rings are closed, i.e. the first point is repeated at the end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon<T> {
+    pub exterior: Vec<T>,
+    pub interiors: Vec<Vec<T>>,
+}
+
+/// Errors that can occur while decoding a geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryError {
+    /// The geometry type is `UNKNOWN`, whose encoding is not defined.
+    UnknownType,
+    /// The geometry ends in the middle of a command.
+    Truncated,
+    /// The command at the index of the geometry field is not allowed there.
+    UnexpectedCommand { index: usize, command: u32 },
+    /// A polygon starts with an interior ring, which has no exterior ring to belong to.
+    InteriorRingFirst,
+}
+
+impl fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryError::UnknownType => write!(f, "unknown geometry type"),
+            GeometryError::Truncated => write!(f, "geometry ends in the middle of a command"),
+            GeometryError::UnexpectedCommand { index, command } => {
+                write!(f, "unexpected command {command} at index {index}")
+            }
+            GeometryError::InteriorRingFirst => {
+                write!(f, "polygon starts with an interior ring")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+/// Decodes the geometry of the feature in tile-local coordinates, where x grows eastward and y
+/// grows southward from 0 to the extent of the layer.
+pub fn decode_geometry(feature: &Feature) -> Result<Geometry<(i32, i32)>, GeometryError> {
+    let paths = decode_paths(&feature.geometry)?;
+
+    match feature.r#type() {
+        GeomType::Unknown => Err(GeometryError::UnknownType),
+        GeomType::Point => Ok(Geometry::Points(
+            paths.into_iter().flat_map(|p| p.points).collect(),
+        )),
+        GeomType::Linestring => Ok(Geometry::LineStrings(
+            paths.into_iter().map(|p| p.points).collect(),
+        )),
+        GeomType::Polygon => {
+            let mut polygons: Vec<Polygon<(i32, i32)>> = vec![];
+            for mut path in paths {
+                let area = signed_area(&path.points);
+                if area == 0 {
+                    // degenerate rings have no winding to tell exterior from interior
+                    continue;
+                }
+                if let Some(&first) = path.points.first() {
+                    path.points.push(first);
+                }
+
+                // exterior rings are clockwise in tile coordinates, i.e. have positive area
+                if area > 0 {
+                    polygons.push(Polygon {
+                        exterior: path.points,
+                        interiors: vec![],
+                    });
+                } else {
+                    polygons
+                        .last_mut()
+                        .ok_or(GeometryError::InteriorRingFirst)?
+                        .interiors
+                        .push(path.points);
+                }
+            }
+            Ok(Geometry::Polygons(polygons))
+        }
+    }
+}
+
+/// A sequence of points starting with a MoveTo
+struct Path {
+    points: Vec<(i32, i32)>,
+}
+
+fn decode_paths(geometry: &[u32]) -> Result<Vec<Path>, GeometryError> {
+    let mut paths: Vec<Path> = vec![];
+    // coordinates are relative to the previous point, across paths
+    let (mut x, mut y) = (0i32, 0i32);
+
+    let mut i = 0;
+    while i < geometry.len() {
+        let index = i;
+        let command = geometry[i] & 0x7;
+        let count = (geometry[i] >> 3) as usize;
+        i += 1;
+
+        match command {
+            MOVE_TO | LINE_TO => {
+                let params = geometry
+                    .get(i..i + 2 * count)
+                    .ok_or(GeometryError::Truncated)?;
+                i += 2 * count;
+
+                if command == LINE_TO && paths.is_empty() {
+                    return Err(GeometryError::UnexpectedCommand { index, command });
+                }
+
+                for dxy in params.chunks_exact(2) {
+                    x = x.wrapping_add(zigzag(dxy[0]));
+                    y = y.wrapping_add(zigzag(dxy[1]));
+                    // each point of a MoveTo starts a path (a MoveTo has multiple points only in
+                    // multi-points)
+                    if command == MOVE_TO {
+                        paths.push(Path { points: vec![] });
+                    }
+                    paths.last_mut().unwrap().points.push((x, y));
+                }
+            }
+            CLOSE_PATH => {
+                if paths.is_empty() {
+                    return Err(GeometryError::UnexpectedCommand { index, command });
+                }
+            }
+            _ => return Err(GeometryError::UnexpectedCommand { index, command }),
+        }
+    }
+
+    Ok(paths)
+}
+
+fn zigzag(n: u32) -> i32 {
+    ((n >> 1) as i32) ^ -((n & 1) as i32)
+}
+
+/// Twice the area of the ring by the surveyor's formula
+fn signed_area(ring: &[(i32, i32)]) -> i64 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| {
+            let (x0, y0) = ring[i];
+            let (x1, y1) = ring[(i + 1) % n];
+            x0 as i64 * y1 as i64 - x1 as i64 * y0 as i64
+        })
+        .sum()
+}
+
+impl<T> Geometry<T> {
+    /// Converts each point of the geometry.
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> Geometry<U> {
+        let map_all = |points: &Vec<T>| points.iter().map(&f).collect();
+        match self {
+            Geometry::Points(points) => Geometry::Points(map_all(points)),
+            Geometry::LineStrings(lines) => {
+                Geometry::LineStrings(lines.iter().map(map_all).collect())
+            }
+            Geometry::Polygons(polygons) => Geometry::Polygons(
+                polygons
+                    .iter()
+                    .map(|p| Polygon {
+                        exterior: map_all(&p.exterior),
+                        interiors: p.interiors.iter().map(map_all).collect(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Geometry<(i32, i32)> {
+    /// Converts the tile-local coordinates to WGS84 `(lon, lat)`, given the tile and the
+    /// extent of its layer. Rings keep their order, so exterior rings stay clockwise on the map.
+    pub fn to_wgs84(&self, z: u8, x: u32, y: u32, extent: u32) -> Geometry<(f64, f64)> {
+        self.map(|&point| tile_to_wgs84(z, x, y, extent, point))
+    }
+}
+
+/// Converts a point in the tile-local coordinates to WGS84 `(lon, lat)`.
+pub fn tile_to_wgs84(z: u8, x: u32, y: u32, extent: u32, (px, py): (i32, i32)) -> (f64, f64) {
+    let n = (1u64 << z) as f64;
+    let extent = extent as f64;
+    // the position in the whole map, from 0 to 1
+    let mx = (x as f64 + px as f64 / extent) / n;
+    let my = (y as f64 + py as f64 / extent) / n;
+
+    let lon = mx * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * my)).sinh().atan().to_degrees();
+    (lon, lat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the examples in section 4.3.5 of the spec
+    fn feature(r#type: GeomType, geometry: &[u32]) -> Feature {
+        let mut feature = Feature {
+            geometry: geometry.to_vec(),
+            ..Default::default()
+        };
+        feature.set_type(r#type);
+        feature
+    }
+
+    #[test]
+    fn test_decode_points() {
+        let f = feature(GeomType::Point, &[9, 50, 34]);
+        assert_eq!(decode_geometry(&f), Ok(Geometry::Points(vec![(25, 17)])));
+
+        let f = feature(GeomType::Point, &[17, 10, 14, 3, 9]);
+        assert_eq!(
+            decode_geometry(&f),
+            Ok(Geometry::Points(vec![(5, 7), (3, 2)]))
+        );
+    }
+
+    #[test]
+    fn test_decode_linestrings() {
+        let f = feature(GeomType::Linestring, &[9, 4, 4, 18, 0, 16, 16, 0]);
+        assert_eq!(
+            decode_geometry(&f),
+            Ok(Geometry::LineStrings(vec![vec![(2, 2), (2, 10), (10, 10)]]))
+        );
+
+        let f = feature(
+            GeomType::Linestring,
+            &[9, 4, 4, 18, 0, 16, 16, 0, 9, 17, 17, 10, 4, 8],
+        );
+        assert_eq!(
+            decode_geometry(&f),
+            Ok(Geometry::LineStrings(vec![
+                vec![(2, 2), (2, 10), (10, 10)],
+                vec![(1, 1), (3, 5)],
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_decode_polygons() {
+        let f = feature(GeomType::Polygon, &[9, 6, 12, 18, 10, 12, 24, 44, 15]);
+        assert_eq!(
+            decode_geometry(&f),
+            Ok(Geometry::Polygons(vec![Polygon {
+                exterior: vec![(3, 6), (8, 12), (20, 34), (3, 6)],
+                interiors: vec![],
+            }]))
+        );
+
+        let f = feature(
+            GeomType::Polygon,
+            &[
+                9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15, 9, 22, 2, 26, 18, 0, 0, 18, 17, 0, 15, 9, 4,
+                13, 26, 0, 8, 8, 0, 0, 7, 15,
+            ],
+        );
+        assert_eq!(
+            decode_geometry(&f),
+            Ok(Geometry::Polygons(vec![
+                Polygon {
+                    exterior: vec![(0, 0), (10, 0), (10, 10), (0, 10), (0, 0)],
+                    interiors: vec![],
+                },
+                Polygon {
+                    exterior: vec![(11, 11), (20, 11), (20, 20), (11, 20), (11, 11)],
+                    interiors: vec![vec![(13, 13), (13, 17), (17, 17), (17, 13), (13, 13)]],
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let f = feature(GeomType::Point, &[9, 50]);
+        assert_eq!(decode_geometry(&f), Err(GeometryError::Truncated));
+
+        let f = feature(GeomType::Linestring, &[18, 0, 16, 16, 0]);
+        assert_eq!(
+            decode_geometry(&f),
+            Err(GeometryError::UnexpectedCommand {
+                index: 0,
+                command: LINE_TO
+            })
+        );
+
+        let f = feature(GeomType::Point, &[9, 50, 34, 3]);
+        assert_eq!(
+            decode_geometry(&f),
+            Err(GeometryError::UnexpectedCommand {
+                index: 3,
+                command: 3
+            })
+        );
+
+        // counterclockwise, i.e. an interior ring
+        let f = feature(GeomType::Polygon, &[9, 0, 0, 26, 0, 20, 20, 0, 0, 19, 15]);
+        assert_eq!(decode_geometry(&f), Err(GeometryError::InteriorRingFirst));
+
+        let f = feature(GeomType::Unknown, &[9, 50, 34]);
+        assert_eq!(decode_geometry(&f), Err(GeometryError::UnknownType));
+    }
+
+    #[test]
+    fn test_to_wgs84() {
+        let assert_close = |(lon, lat): (f64, f64), expected: (f64, f64)| {
+            assert!((lon - expected.0).abs() < 1e-6, "{lon} != {}", expected.0);
+            assert!((lat - expected.1).abs() < 1e-6, "{lat} != {}", expected.1);
+        };
+
+        assert_close(tile_to_wgs84(0, 0, 0, 4096, (2048, 2048)), (0.0, 0.0));
+        assert_close(
+            tile_to_wgs84(0, 0, 0, 4096, (0, 0)),
+            (-180.0, 85.0511287798066),
+        );
+        // the north-west corner of 12/3637/1612 in Tokyo
+        assert_close(
+            tile_to_wgs84(12, 3637, 1612, 4096, (0, 0)),
+            (139.658203125, 35.74651225991853),
+        );
+
+        let f = feature(GeomType::Point, &[9, 8192, 8192]);
+        let geometry = decode_geometry(&f).unwrap().to_wgs84(1, 0, 0, 4096);
+        let Geometry::Points(points) = geometry else {
+            panic!("not points: {geometry:?}");
+        };
+        assert_close(points[0], (0.0, 0.0));
+    }
+}
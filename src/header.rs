@@ -1,3 +1,4 @@
+use crate::Error;
 use crate::util;
 
 use nom::IResult;
@@ -6,7 +7,7 @@ use nom::number::complete::le_i32;
 use nom::number::complete::le_u8;
 use nom::number::complete::le_u64;
 
-pub(crate) const HEADER_BYTES: usize = 127;
+pub const HEADER_BYTES: usize = 127;
 
 /// PMTiles V3 Header Data
 ///
@@ -57,8 +58,37 @@ pub enum PMTilesTileType {
 
 #[derive(Debug, PartialEq)]
 pub struct PMTilesPosition {
-    pub(crate) lon: f32,
-    pub(crate) lat: f32,
+    pub lon: f32,
+    pub lat: f32,
+}
+
+/// Parses the header like [`parse_header`], but tells what is wrong with the input on failure.
+pub fn read_header(input: &[u8]) -> Result<PMTilesHeaderV3, Error> {
+    if input.len() < HEADER_BYTES {
+        return Err(Error::Io {
+            offset: input.len() as u64,
+            source: std::io::ErrorKind::UnexpectedEof.into(),
+        });
+    }
+    if !input.starts_with(b"PMTiles") {
+        return Err(Error::BadMagic);
+    }
+    if input[7] != 3 {
+        return Err(Error::UnsupportedVersion(input[7]));
+    }
+
+    match parse_header(input) {
+        Ok((_, header)) => Ok(header),
+        // Since the length is checked above, the only possible failure is an invalid enum byte
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+            let offset = input.len() - e.input.len();
+            Err(Error::InvalidHeaderValue {
+                offset: offset as u64,
+                value: input[offset],
+            })
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never return Incomplete"),
+    }
 }
 
 pub fn parse_header(input: &[u8]) -> IResult<&[u8], PMTilesHeaderV3> {
@@ -114,17 +144,17 @@ pub fn parse_header(input: &[u8]) -> IResult<&[u8], PMTilesHeaderV3> {
 }
 
 pub(crate) fn parse_clustered(input: &[u8]) -> IResult<&[u8], bool> {
-    let (input, clustered_raw) = le_u8(input)?;
+    let (rest, clustered_raw) = le_u8(input)?;
     let clustered = match clustered_raw {
         0 => false,
         1 => true,
         _ => return util::nom_error(input),
     };
-    Ok((input, clustered))
+    Ok((rest, clustered))
 }
 
 pub(crate) fn parse_compression(input: &[u8]) -> IResult<&[u8], PMTilesCompression> {
-    let (input, compression_raw) = le_u8(input)?;
+    let (rest, compression_raw) = le_u8(input)?;
     let compression = match compression_raw {
         0 => PMTilesCompression::Unknown,
         1 => PMTilesCompression::None,
@@ -133,11 +163,11 @@ pub(crate) fn parse_compression(input: &[u8]) -> IResult<&[u8], PMTilesCompressi
         4 => PMTilesCompression::Zstd,
         _ => return util::nom_error(input),
     };
-    Ok((input, compression))
+    Ok((rest, compression))
 }
 
 pub(crate) fn parse_tile_type(input: &[u8]) -> IResult<&[u8], PMTilesTileType> {
-    let (input, tile_type_raw) = le_u8(input)?;
+    let (rest, tile_type_raw) = le_u8(input)?;
     let tile_type = match tile_type_raw {
         0 => PMTilesTileType::Other,
         1 => PMTilesTileType::Mvt,
@@ -147,7 +177,7 @@ pub(crate) fn parse_tile_type(input: &[u8]) -> IResult<&[u8], PMTilesTileType> {
         5 => PMTilesTileType::Avif,
         _ => return util::nom_error(input),
     };
-    Ok((input, tile_type))
+    Ok((rest, tile_type))
 }
 
 pub(crate) fn parse_position(input: &[u8]) -> IResult<&[u8], PMTilesPosition> {
@@ -180,7 +210,7 @@ mod tests {
         assert_eq!(result.number_of_addressed_tiles, 1);
         assert_eq!(result.number_of_tile_entries, 1);
         assert_eq!(result.number_of_tile_contents, 1);
-        assert_eq!(result.clustered, false);
+        assert!(!result.clustered);
         assert_eq!(result.internal_compression, PMTilesCompression::Gzip);
         assert_eq!(result.tile_compression, PMTilesCompression::Gzip);
         assert_eq!(result.tile_type, PMTilesTileType::Mvt);
@@ -194,4 +224,36 @@ mod tests {
         assert_eq!(result.center_position.lon, 0.0);
         assert_eq!(result.center_position.lat, 0.0);
     }
+
+    #[test]
+    fn test_read_header_errors() {
+        let data = include_bytes!("./test/test_fixture_1.pmtiles");
+
+        let mut bad_magic = data[..127].to_vec();
+        bad_magic[0] = b'X';
+        assert!(matches!(read_header(&bad_magic), Err(Error::BadMagic)));
+
+        let mut bad_version = data[..127].to_vec();
+        bad_version[7] = 2;
+        assert!(matches!(
+            read_header(&bad_version),
+            Err(Error::UnsupportedVersion(2))
+        ));
+
+        // tile_type
+        let mut bad_enum = data[..127].to_vec();
+        bad_enum[99] = 42;
+        assert!(matches!(
+            read_header(&bad_enum),
+            Err(Error::InvalidHeaderValue {
+                offset: 99,
+                value: 42
+            })
+        ));
+
+        assert!(matches!(
+            read_header(&data[..100]),
+            Err(Error::Io { offset: 100, .. })
+        ));
+    }
 }
@@ -0,0 +1,35 @@
+//! A reader for [PMTiles v3](https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md)
+//! archives of Mapbox Vector Tiles.
+//!
+//! [`PMTilesFile`] reads the header, the metadata, the root and leaf directories, and the
+//! tiles from any [`RangeReader`]. The lower-level parsers are available in [`header`] and
+//! [`directory`].
+
+pub mod bbox;
+pub mod coverage;
+pub mod directory;
+mod error;
+pub mod geojson;
+pub mod geometry;
+pub mod header;
+pub mod output;
+pub mod reader;
+pub mod scan;
+pub mod script;
+pub mod subset;
+#[cfg(test)]
+mod test_util;
+pub mod tile_id;
+pub mod traverse;
+mod unicode_blocks;
+mod util;
+mod varint;
+
+/// Mapbox Vector Tile types generated from `mvt.proto`.
+pub mod mvt {
+    include!(concat!(env!("OUT_DIR"), "/vector_tile.rs"));
+}
+
+pub use error::Error;
+pub use reader::{MmapReader, RangeReader};
+pub use util::{PMTilesFile, decode_tile};
@@ -1,23 +1,26 @@
 use std::io::Write as _;
 use std::{collections::HashMap, error::Error};
 
-use clap::{Parser, Subcommand};
-use indicatif::ProgressBar;
-use util::PMTilesFile;
-
-mod directory;
-mod header;
-mod util;
-mod varint;
-
-mod mvt {
-    include!(concat!(env!("OUT_DIR"), "/vector_tile.rs"));
-}
+use clap::{Args, CommandFactory as _, Parser, Subcommand, ValueEnum};
+use dump_pmtiles_labels::{
+    Error as PMTilesError, PMTilesFile, RangeReader,
+    bbox::BBox,
+    coverage, decode_tile,
+    directory::PMTilesEntry,
+    geojson, header, mvt,
+    output::{self, OutputFormat},
+    scan, script, subset, tile_id,
+    traverse::TilesOptions,
+};
+use indicatif::{ProgressBar, ProgressStyle};
 
 #[derive(Parser, Debug)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Read the local file via memory mapping instead of seek and read
+    #[arg(long, global = true)]
+    mmap: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -32,69 +35,323 @@ enum Commands {
         file: std::path::PathBuf,
         #[arg(long, default_value_t = 10)]
         limit: usize,
+        /// Only list the tiles intersecting minlon,minlat,maxlon,maxlat
+        #[arg(long, allow_hyphen_values = true)]
+        bbox: Option<BBox>,
     },
     Tile {
         file: std::path::PathBuf,
-        offset: u64,
-        length: usize,
+        z: u8,
+        x: u32,
+        y: u32,
+        /// Number of layers, and features in each layer, to print in the text format
         #[arg(long, default_value_t = 10)]
         limit: usize,
+        #[arg(long, value_enum, default_value_t = TileFormat::Text)]
+        format: TileFormat,
     },
     Text {
         file: std::path::PathBuf,
-        #[arg(long)]
-        limit: Option<usize>,
-        #[arg(long, short, default_value = "out.csv")]
-        output: std::path::PathBuf,
+        #[command(flatten)]
+        scan: ScanArgs,
     },
     Char {
         file: std::path::PathBuf,
-        #[arg(long)]
-        limit: Option<usize>,
-        #[arg(long, short, default_value = "out.csv")]
-        output: std::path::PathBuf,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        char: CharArgs,
+    },
+    Geojson {
+        file: std::path::PathBuf,
+        #[command(flatten)]
+        export: GeojsonArgs,
     },
+    /// List the characters of the labels that a TTF/OTF font has no glyph for
+    Coverage {
+        file: std::path::PathBuf,
+        #[command(flatten)]
+        scan: ScanArgs,
+        #[command(flatten)]
+        coverage: CoverageArgs,
+    },
+}
+
+impl Commands {
+    /// Checks the constraints between arguments that clap can't express.
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Commands::Text { scan, .. }
+            | Commands::Char { scan, .. }
+            | Commands::Coverage { scan, .. } => check_zoom_range(scan.min_zoom, scan.max_zoom),
+            Commands::Geojson { export, .. } => check_zoom_range(export.min_zoom, export.max_zoom),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_zoom_range(min_zoom: u8, max_zoom: u8) -> Result<(), String> {
+    if min_zoom > max_zoom {
+        return Err(format!(
+            "--min-zoom {min_zoom} is greater than --max-zoom {max_zoom}"
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum TileFormat {
+    /// Human-readable list of layers and features
+    Text,
+    /// All the layers and features as a JSON object
+    Json,
+}
+
+/// Options shared by the commands that scan all the tiles
+#[derive(Debug, Args)]
+struct ScanArgs {
+    #[arg(long)]
+    limit: Option<usize>,
+    #[arg(long, short, default_value = "out.csv")]
+    output: std::path::PathBuf,
+    /// Output format: csv, tsv or jsonl (JSON Lines)
+    #[arg(long, default_value = "csv")]
+    format: OutputFormat,
+    /// Number of threads to read tiles with
+    #[arg(long, short, default_value_t = 1)]
+    jobs: usize,
+    /// Count the strings in tile data shared by multiple tiles only once, instead of once per
+    /// tile. Either way, shared data is decoded only once.
+    #[arg(long)]
+    count_shared_once: bool,
+    /// Count how many features reference each string, instead of how many layers contain it.
+    /// MVT layers store each distinct value once, so the default ignores how often a label
+    /// actually appears on the map.
+    #[arg(long)]
+    per_feature: bool,
+    /// Only count the layers matching the pattern, in which `*` and `?` are wildcards. Can be
+    /// repeated.
+    #[arg(long = "layer", value_name = "PATTERN")]
+    layers: Vec<String>,
+    /// Don't count the layers matching the pattern. Can be repeated.
+    #[arg(long = "exclude-layer", value_name = "PATTERN")]
+    exclude_layers: Vec<String>,
+    /// Only count the values of the key (e.g. vt_text) referenced by features. Can be repeated.
+    #[arg(long = "key", value_name = "KEY")]
+    keys: Vec<String>,
+    /// Skip the tiles below this zoom level
+    #[arg(long, default_value_t = 0)]
+    min_zoom: u8,
+    /// Skip the tiles above this zoom level
+    #[arg(long, default_value_t = tile_id::MAX_ZOOM)]
+    max_zoom: u8,
+    /// Only count the tiles intersecting minlon,minlat,maxlon,maxlat at each zoom level
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<BBox>,
+}
+
+/// Options only for the char command
+#[derive(Debug, Args)]
+struct CharArgs {
+    /// Also write the characters as plain text in code point order, e.g. for
+    /// `pyftsubset --text-file`
+    #[arg(long, value_name = "PATH")]
+    emit_subset_text: Option<std::path::PathBuf>,
+    /// Also write the characters as U+XXXX ranges, one per line, e.g. for
+    /// `pyftsubset --unicodes-file`
+    #[arg(long, value_name = "PATH")]
+    emit_unicodes: Option<std::path::PathBuf>,
+    /// Also write which glyph PBF ranges of 256 code points (e.g. 0-255.pbf) MapLibre needs, with
+    /// the number of distinct characters and occurrences in each, in the format of --format.
+    /// Characters above U+FFFF, which MapLibre can't render, are written as "unsupported".
+    #[arg(long, value_name = "PATH")]
+    emit_glyph_ranges: Option<std::path::PathBuf>,
+    /// Also write the number of distinct characters and occurrences by script (Han, Hiragana,
+    /// Katakana, ...), with Han characters split by JIS X 0208 level, in the format of --format
+    #[arg(long, value_name = "PATH")]
+    emit_script_summary: Option<std::path::PathBuf>,
+    /// Also write the number of distinct characters and occurrences by Unicode block (e.g. CJK
+    /// Unified Ideographs), in the format of --format
+    #[arg(long, value_name = "PATH")]
+    emit_block_summary: Option<std::path::PathBuf>,
+    /// Only emit the characters counted at least this many times
+    #[arg(long)]
+    min_count: Option<usize>,
+    /// Only emit the most frequent characters that account for this percentage of all the
+    /// occurrences
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    coverage: Option<f64>,
+}
+
+/// Options only for the coverage command
+#[derive(Debug, Args)]
+struct CoverageArgs {
+    /// Local TTF/OTF/TTC file whose cmap table is checked
+    #[arg(long)]
+    font: std::path::PathBuf,
+    /// Index of the font in a font collection (TTC)
+    #[arg(long, default_value_t = 0)]
+    font_index: u32,
+    /// Number of the most frequent labels containing each missing character to show
+    #[arg(long, default_value_t = 3)]
+    samples: usize,
+}
+
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(v) if (0.0..=100.0).contains(&v) => Ok(v),
+        _ => Err(format!("expected a percentage from 0 to 100: {s}")),
+    }
+}
+
+/// Options of the geojson command. Without `--tile`, all the tiles in the zoom range and the
+/// bbox are exported.
+#[derive(Debug, Args)]
+struct GeojsonArgs {
+    /// Only export the tile
+    #[arg(long, value_name = "Z/X/Y", value_parser = parse_zxy, conflicts_with_all = ["min_zoom", "max_zoom", "bbox"])]
+    tile: Option<(u8, u32, u32)>,
+    #[arg(long, default_value_t = 0)]
+    min_zoom: u8,
+    #[arg(long, default_value_t = tile_id::MAX_ZOOM)]
+    max_zoom: u8,
+    /// Only export the tiles intersecting minlon,minlat,maxlon,maxlat at each zoom level
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<BBox>,
+    /// Write one feature per line (newline-delimited GeoJSON) instead of a FeatureCollection,
+    /// which is easier to process for large extracts
+    #[arg(long)]
+    ndjson: bool,
+    #[arg(long, short, default_value = "out.geojson")]
+    output: std::path::PathBuf,
+}
+
+fn parse_zxy(s: &str) -> Result<(u8, u32, u32), String> {
+    let parts: Vec<&str> = s.split('/').collect();
+    let [z, x, y] = parts[..] else {
+        return Err(format!("expected Z/X/Y: {s}"));
+    };
+    let invalid = |e: std::num::ParseIntError| format!("invalid tile {s}: {e}");
+    Ok((
+        z.parse().map_err(invalid)?,
+        x.parse().map_err(invalid)?,
+        y.parse().map_err(invalid)?,
+    ))
+}
+
+fn format_zxy(tile_id: u64) -> String {
+    match tile_id::tile_id_to_zxy(tile_id) {
+        Some((z, x, y)) => format!("{z}/{x}/{y}"),
+        None => format!("(invalid tile_id: {tile_id})"),
+    }
+}
+
+fn progress_bar(len: u64) -> ProgressBar {
+    // the default template plus the z/x/y of the tile being processed
+    let style = ProgressStyle::with_template("{wide_bar} {pos}/{len} {msg}").unwrap();
+    ProgressBar::new(len).with_style(style)
 }
 
-fn show_header(file: &PMTilesFile) {
+fn format_tile_value(value: &mvt::tile::Value) -> String {
+    // the same value as in the GeoJSON properties, so that both outputs agree
+    match geojson::value_to_json(value) {
+        serde_json::Value::Null => "(null)".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Looks up the entry of the tile. Returns `None` after printing why if the coordinates are
+/// invalid or the archive doesn't contain the tile.
+fn find_tile<R: RangeReader>(
+    file: &mut PMTilesFile<R>,
+    (z, x, y): (u8, u32, u32),
+) -> Result<Option<PMTilesEntry>, PMTilesError> {
+    let Some(tile_id) = tile_id::zxy_to_tile_id(z, x, y) else {
+        println!("Invalid tile coordinates: {z}/{x}/{y}");
+        return Ok(None);
+    };
+
+    let entry = file.find_tile_entry(tile_id)?;
+    if entry.is_none() {
+        println!("Tile {z}/{x}/{y} (tile_id: {tile_id}) is not present in the archive");
+    }
+    Ok(entry)
+}
+
+/// Clips the bbox, if any, to the bounds of the archive. Returns `None` after printing why if
+/// they don't overlap, and `Some(None)` without a bbox.
+fn clip_bbox<R: RangeReader>(file: &PMTilesFile<R>, bbox: Option<BBox>) -> Option<Option<BBox>> {
+    let Some(bbox) = bbox else {
+        return Some(None);
+    };
+    match bbox.clip_to_header(file.parse_header()) {
+        Some(bbox) => Some(Some(bbox)),
+        None => {
+            println!("The archive has no tiles in the bbox");
+            None
+        }
+    }
+}
+
+fn show_header<R: RangeReader>(file: &PMTilesFile<R>) {
     println!("{:#?}", file.parse_header());
 }
 
-fn show_metadata(file: &mut PMTilesFile) -> Result<(), Box<dyn Error>> {
+fn show_metadata<R: RangeReader>(file: &mut PMTilesFile<R>) -> Result<(), Box<dyn Error>> {
     println!("{}", file.parse_metadata()?);
     Ok(())
 }
 
-fn list_entries(file: &mut PMTilesFile, limit: usize) -> Result<(), Box<dyn Error>> {
-    let entries = file.parse_root_directory()?;
+fn list_entries<R: RangeReader>(
+    file: &mut PMTilesFile<R>,
+    limit: usize,
+    bbox: Option<BBox>,
+) -> Result<(), Box<dyn Error>> {
+    let Some(bbox) = clip_bbox(file, bbox) else {
+        return Ok(());
+    };
 
-    for e in entries.iter().take(limit) {
-        println!("{e:?}");
+    // the number of entries listed in each directory being visited, by depth
+    let mut n_listed: Vec<usize> = vec![];
 
-        if !e.is_tile {
-            let leaf_entries = file.parse_leaf_directory(e.offset, e.length as usize)?;
+    let mut traverse = file.traverse()?;
+    while let Some(item) = traverse.next() {
+        let item = item?;
+        let e = &item.entry;
+        if e.is_tile && bbox.is_some_and(|b| !b.intersects_tile_id(e.tile_id)) {
+            continue;
+        }
 
-            for le in leaf_entries.iter().take(limit) {
-                println!("└── {le:?}");
-            }
-            if leaf_entries.len() > limit {
-                println!("    ...");
-            }
+        n_listed.truncate(item.depth + 1);
+        n_listed.resize(item.depth + 1, 0);
+        n_listed[item.depth] += 1;
+
+        if n_listed[item.depth] > limit {
+            println!("{}...", "    ".repeat(item.depth));
+            traverse.skip_siblings();
+            continue;
         }
-    }
 
-    if entries.len() > limit {
-        println!("...");
+        match item.depth {
+            0 => println!("{} {e:?}", format_zxy(e.tile_id)),
+            depth => println!(
+                "{}└── {} {e:?}",
+                "    ".repeat(depth - 1),
+                format_zxy(e.tile_id)
+            ),
+        }
     }
 
     Ok(())
 }
 
-fn dump_single_tile(
-    file: &mut PMTilesFile,
-    offset: u64,
-    length: usize,
+fn dump_single_tile<R: RangeReader>(
+    file: &mut PMTilesFile<R>,
+    z: u8,
+    x: u32,
+    y: u32,
     limit: usize,
+    format: TileFormat,
 ) -> Result<(), Box<dyn Error>> {
     let tile_type = &file.parse_header().tile_type;
     if !matches!(tile_type, header::PMTilesTileType::Mvt) {
@@ -102,7 +359,39 @@ fn dump_single_tile(
         return Ok(());
     }
 
-    let tile = file.parse_tile(offset, length)?;
+    let Some(entry) = find_tile(file, (z, x, y))? else {
+        return Ok(());
+    };
+
+    let tile = file.parse_tile(entry.offset, entry.length as usize)?;
+
+    if let TileFormat::Json = format {
+        let layers: Vec<serde_json::Value> = tile
+            .layers
+            .iter()
+            .map(|layer| {
+                let features: Vec<serde_json::Value> = layer
+                    .features
+                    .iter()
+                    .map(|feature| {
+                        serde_json::json!({
+                            "id": feature.id,
+                            "type": format!("{:?}", feature.r#type()),
+                            "properties": geojson::properties(layer, feature),
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "name": layer.name,
+                    "extent": layer.extent(),
+                    "features": features,
+                })
+            })
+            .collect();
+        let json = serde_json::json!({ "z": z, "x": x, "y": y, "layers": layers });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
 
     for layer in tile.layers.iter().take(limit) {
         println!("---------------------------------------------------");
@@ -112,154 +401,338 @@ fn dump_single_tile(
         println!("features:");
         for feature in layer.features.iter().take(limit) {
             println!("  - id: {:?}, type: {:?}", feature.id, feature.r#type());
+
+            // tags are pairs of indices into keys and values
+            for kv in feature.tags.chunks_exact(2) {
+                let key = layer.keys.get(kv[0] as usize);
+                let value = layer.values.get(kv[1] as usize);
+                if let (Some(key), Some(value)) = (key, value) {
+                    println!("    {key}: {}", format_tile_value(value));
+                }
+            }
         }
         if layer.features.len() > limit {
             println!("    ...");
         }
+    }
 
-        println!("keys: {:?}", layer.keys);
+    Ok(())
+}
 
-        print!("values: [");
-        for value in layer.values.iter().take(limit) {
-            util::print_tile_value(value);
-        }
-        if layer.values.len() > limit {
-            print!("...");
+fn scan_strings<R, F>(open: F, args: &ScanArgs) -> Result<HashMap<String, usize>, Box<dyn Error>>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, PMTilesError> + Sync,
+{
+    let options = scan::ScanOptions {
+        jobs: args.jobs,
+        count_shared_once: args.count_shared_once,
+        per_feature: args.per_feature,
+        layers: args.layers.clone(),
+        exclude_layers: args.exclude_layers.clone(),
+        keys: args.keys.clone(),
+        min_zoom: args.min_zoom,
+        max_zoom: args.max_zoom,
+        bbox: args.bbox,
+    };
+    // the number of tiles is unknown until all the directories are read
+    let spinner = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} {pos} tiles {msg}").unwrap());
+    let contents = scan::collect_tile_contents(&mut open()?, args.limit, &options, &|item| {
+        if item.entry.is_tile {
+            spinner.inc(1);
+        } else {
+            spinner.set_message(format!(
+                "reading the leaf directory at {}",
+                format_zxy(item.entry.tile_id)
+            ));
         }
-        println!("]");
-    }
+    })?;
+    spinner.finish_and_clear();
 
-    Ok(())
+    let bar = progress_bar(contents.len() as u64);
+    let result = scan::count_strings(open, &contents, &options, &|c| {
+        bar.set_message(format_zxy(c.entry.tile_id));
+        bar.inc(1);
+    })?;
+    bar.finish();
+
+    Ok(result)
 }
 
-fn dump_text<P: AsRef<std::path::Path>>(
-    file: &mut PMTilesFile,
-    output: P,
-    limit: Option<usize>,
-) -> Result<(), Box<dyn Error>> {
-    let limit = limit.unwrap_or(usize::MAX);
-    let mut out = std::fs::File::create(output.as_ref())?;
+fn dump_text<R, F>(open: F, args: &ScanArgs) -> Result<(), Box<dyn Error>>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, PMTilesError> + Sync,
+{
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
 
-    let entries = file.parse_root_directory()?;
+    let result = scan_strings(open, args)?;
 
-    let mut result: HashMap<String, usize> = HashMap::new();
-    let bar = ProgressBar::new(entries.len() as u64);
+    // show result
 
-    for e in entries.into_iter().take(limit) {
-        let leaf_entries = if e.is_tile {
-            vec![e]
-        } else {
-            // if the entry in the root directory points to a leaf directory, parse it
-            file.parse_leaf_directory(e.offset, e.length as usize)?
-        };
+    let sorted = scan::sort_counts(result);
+    output::write_counts(&mut out, args.format, &sorted)?;
+    out.flush()?;
 
-        for le in &leaf_entries {
-            let tile = file.parse_tile(le.offset, le.length as usize)?;
-
-            for l in tile.layers {
-                for v in l.values {
-                    match v.string_value {
-                        Some(s) => {
-                            let count = result.entry(s).or_insert(0);
-                            *count += 1;
-                        }
-                        None => {}
-                    }
-                }
-            }
-        }
-        bar.inc(1);
-    }
+    Ok(())
+}
+
+fn dump_char<R, F>(open: F, args: &ScanArgs, char_args: &CharArgs) -> Result<(), Box<dyn Error>>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, PMTilesError> + Sync,
+{
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
+
+    let result = scan::count_chars(&scan_strings(open, args)?);
 
     // show result
 
-    let mut sorted: Vec<(String, usize)> = result.into_iter().collect();
-    sorted.sort_by(|a, b| b.1.cmp(&a.1)); // reverse sort
-    writeln!(&mut out, r#""text","count""#)?;
-    for (k, v) in &sorted {
-        writeln!(&mut out, r#""{k}",{v}"#)?;
+    let sorted = scan::sort_counts(result);
+    output::write_counts(&mut out, args.format, &sorted)?;
+    out.flush()?;
+
+    // font subsetting
+
+    let selected = subset::select_chars(&sorted, char_args.min_count, char_args.coverage);
+    if let Some(path) = &char_args.emit_subset_text {
+        std::fs::write(path, subset::subset_text(&selected))?;
+    }
+    if let Some(path) = &char_args.emit_unicodes {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for range in subset::unicode_ranges(&selected) {
+            writeln!(out, "{}", subset::format_unicode_range(&range))?;
+        }
+        out.flush()?;
+    }
+    // the selected characters are the most frequent ones, i.e. a prefix of sorted
+    let selected_counts = &sorted[..selected.len()];
+    if let Some(path) = &char_args.emit_glyph_ranges {
+        let ranges = subset::glyph_ranges(selected_counts);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        output::write_char_groups(&mut out, args.format, "range", &ranges)?;
+        out.flush()?;
+    }
+    if let Some(path) = &char_args.emit_script_summary {
+        let summaries = script::summarize(selected_counts);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        output::write_char_groups(&mut out, args.format, "category", &summaries)?;
+        out.flush()?;
+    }
+    if let Some(path) = &char_args.emit_block_summary {
+        let summaries = script::summarize_blocks(selected_counts);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        output::write_char_groups(&mut out, args.format, "block", &summaries)?;
+        out.flush()?;
     }
 
     Ok(())
 }
 
-fn dump_char<P: AsRef<std::path::Path>>(
-    file: &mut PMTilesFile,
-    output: P,
-    limit: Option<usize>,
-) -> Result<(), Box<dyn Error>> {
-    let limit = limit.unwrap_or(usize::MAX);
-    let mut out = std::fs::File::create(output.as_ref())?;
+fn check_coverage<R, F>(
+    open: F,
+    args: &ScanArgs,
+    coverage_args: &CoverageArgs,
+) -> Result<(), Box<dyn Error>>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, PMTilesError> + Sync,
+{
+    // fail before scanning if the font is broken
+    let font_data = std::fs::read(&coverage_args.font)?;
+    let face = ttf_parser::Face::parse(&font_data, coverage_args.font_index)
+        .map_err(|e| format!("failed to parse font {}: {e}", coverage_args.font.display()))?;
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
+
+    let strings = scan_strings(open, args)?;
+    let missing = coverage::find_missing_chars(
+        &strings,
+        |c| face.glyph_index(c).is_some(),
+        coverage_args.samples,
+    );
+
+    output::write_missing_chars(&mut out, args.format, &missing)?;
+    out.flush()?;
+
+    // summary
+
+    let chars = scan::count_chars(&strings);
+    let total: usize = chars.values().sum();
+    let total_missing: usize = missing.iter().map(|m| m.count).sum();
+    println!(
+        "{} of {} characters missing, {} of {} occurrences ({:.2}%)",
+        missing.len(),
+        chars.len(),
+        total_missing,
+        total,
+        if total == 0 {
+            0.0
+        } else {
+            total_missing as f64 * 100.0 / total as f64
+        }
+    );
 
-    let entries = file.parse_root_directory()?;
+    Ok(())
+}
 
-    let mut result: HashMap<char, usize> = HashMap::new();
-    let bar = ProgressBar::new(entries.len() as u64);
+/// Writes features either as a FeatureCollection or as newline-delimited GeoJSON.
+struct GeojsonWriter<W: std::io::Write> {
+    out: W,
+    ndjson: bool,
+    n_features: usize,
+}
 
-    for e in entries.into_iter().take(limit) {
-        let leaf_entries = if e.is_tile {
-            vec![e]
-        } else {
-            // if the entry in the root directory points to a leaf directory, parse it
-            file.parse_leaf_directory(e.offset, e.length as usize)?
-        };
+impl<W: std::io::Write> GeojsonWriter<W> {
+    fn new(mut out: W, ndjson: bool) -> std::io::Result<Self> {
+        if !ndjson {
+            writeln!(out, r#"{{"type":"FeatureCollection","features":["#)?;
+        }
+        Ok(Self {
+            out,
+            ndjson,
+            n_features: 0,
+        })
+    }
 
-        for le in &leaf_entries {
-            let tile = file.parse_tile(le.offset, le.length as usize)?;
-
-            for l in tile.layers {
-                for v in l.values {
-                    match v.string_value {
-                        Some(s) => {
-                            for c in s.chars() {
-                                let count = result.entry(c).or_insert(0);
-                                *count += 1;
-                            }
-                        }
-                        None => {}
+    fn write_tile(&mut self, tile: &mvt::Tile, zxy: (u8, u32, u32)) -> std::io::Result<()> {
+        for layer in &tile.layers {
+            for feature in &layer.features {
+                let feature = match geojson::feature_to_json(layer, feature, zxy) {
+                    Ok(feature) => feature,
+                    Err(e) => {
+                        let (z, x, y) = zxy;
+                        eprintln!("Skipped a feature of {} in {z}/{x}/{y}: {e}", layer.name);
+                        continue;
                     }
+                };
+
+                if !self.ndjson && self.n_features > 0 {
+                    writeln!(self.out, ",")?;
+                }
+                write!(self.out, "{feature}")?;
+                if self.ndjson {
+                    writeln!(self.out)?;
                 }
+                self.n_features += 1;
             }
         }
-        bar.inc(1);
+        Ok(())
     }
 
-    // show result
+    fn finish(mut self) -> std::io::Result<usize> {
+        if !self.ndjson {
+            writeln!(self.out, "\n]}}")?;
+        }
+        self.out.flush()?;
+        Ok(self.n_features)
+    }
+}
+
+fn export_geojson<R: RangeReader>(
+    file: &mut PMTilesFile<R>,
+    args: &GeojsonArgs,
+) -> Result<(), Box<dyn Error>> {
+    let tile_type = &file.parse_header().tile_type;
+    if !matches!(tile_type, header::PMTilesTileType::Mvt) {
+        println!("Unsupported tile type: {tile_type:?}");
+        return Ok(());
+    }
+
+    // resolve the tile and the bbox first so as not to leave an unterminated FeatureCollection
+    let tile = match args.tile {
+        Some(zxy) => match find_tile(file, zxy)? {
+            Some(entry) => Some((zxy, entry)),
+            None => return Ok(()),
+        },
+        None => None,
+    };
+    let Some(bbox) = clip_bbox(file, args.bbox) else {
+        return Ok(());
+    };
 
-    let mut sorted: Vec<(char, usize)> = result.into_iter().collect();
-    sorted.sort_by(|a, b| b.1.cmp(&a.1)); // reverse sort
-    writeln!(&mut out, r#""text","count""#)?;
-    for (k, v) in &sorted {
-        writeln!(&mut out, r#""{k}",{v}"#)?;
+    let out = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
+    let mut writer = GeojsonWriter::new(out, args.ndjson)?;
+
+    if let Some((zxy, entry)) = tile {
+        let tile = file.parse_tile(entry.offset, entry.length as usize)?;
+        writer.write_tile(&tile, zxy)?;
+    } else {
+        let options = TilesOptions {
+            min_zoom: args.min_zoom,
+            max_zoom: args.max_zoom,
+            bbox,
+            ..Default::default()
+        };
+        for tile in file.tiles(&options)? {
+            let tile = tile?;
+            writer.write_tile(&decode_tile(&tile.data)?, (tile.z, tile.x, tile.y))?;
+        }
     }
 
+    let n_features = writer.finish()?;
+    println!("Wrote {n_features} features to {}", args.output.display());
+
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Cli::parse();
-    let mut file = match &args.command {
-        Commands::ShowHeader { file } => PMTilesFile::new(file)?,
-        Commands::ShowMetadata { file } => PMTilesFile::new(file)?,
-        Commands::List { file, .. } => PMTilesFile::new(file)?,
-        Commands::Tile { file, .. } => PMTilesFile::new(file)?,
-        Commands::Text { file, .. } => PMTilesFile::new(file)?,
-        Commands::Char { file, .. } => PMTilesFile::new(file)?,
-    };
-
-    match &args.command {
-        Commands::ShowHeader { .. } => show_header(&file),
-        Commands::ShowMetadata { .. } => show_metadata(&mut file)?,
-        Commands::List { limit, .. } => list_entries(&mut file, *limit)?,
+/// Runs the command. `open` is called for each reader needed, e.g. once per thread.
+fn run<R, F>(open: F, command: &Commands) -> Result<(), Box<dyn Error>>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, PMTilesError> + Sync,
+{
+    match command {
+        Commands::ShowHeader { .. } => show_header(&open()?),
+        Commands::ShowMetadata { .. } => show_metadata(&mut open()?)?,
+        Commands::List { limit, bbox, .. } => list_entries(&mut open()?, *limit, *bbox)?,
         Commands::Tile {
-            offset,
-            length,
+            z,
+            x,
+            y,
             limit,
+            format,
             ..
-        } => dump_single_tile(&mut file, *offset, *length, *limit)?,
-        Commands::Text { limit, output, .. } => dump_text(&mut file, output, *limit)?,
-        Commands::Char { limit, output, .. } => dump_char(&mut file, output, *limit)?,
+        } => dump_single_tile(&mut open()?, *z, *x, *y, *limit, *format)?,
+        Commands::Text { scan, .. } => dump_text(open, scan)?,
+        Commands::Char { scan, char, .. } => dump_char(open, scan, char)?,
+        Commands::Geojson { export, .. } => export_geojson(&mut open()?, export)?,
+        Commands::Coverage { scan, coverage, .. } => check_coverage(open, scan, coverage)?,
     };
 
     Ok(())
 }
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Cli::parse();
+    if let Err(message) = args.command.validate() {
+        Cli::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+    let file = match &args.command {
+        Commands::ShowHeader { file } => file,
+        Commands::ShowMetadata { file } => file,
+        Commands::List { file, .. } => file,
+        Commands::Tile { file, .. } => file,
+        Commands::Text { file, .. } => file,
+        Commands::Char { file, .. } => file,
+        Commands::Geojson { file, .. } => file,
+        Commands::Coverage { file, .. } => file,
+    };
+
+    #[cfg(feature = "http")]
+    if let Some(url) = file
+        .to_str()
+        .filter(|f| f.starts_with("http://") || f.starts_with("https://"))
+    {
+        return run(|| PMTilesFile::open_url(url), &args.command);
+    }
+
+    if args.mmap {
+        return run(|| PMTilesFile::open_mmap(file), &args.command);
+    }
+
+    run(|| PMTilesFile::new(file), &args.command)
+}
@@ -0,0 +1,294 @@
+//! Writing the counts of strings or characters, summaries of them, or the characters missing
+//! from a font, as CSV, TSV or JSON Lines.
+
+use std::{fmt::Display, io, str::FromStr};
+
+use crate::{coverage::MissingChar, scan::CharGroup};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// RFC 4180 CSV. Texts are always quoted.
+    Csv,
+    /// Tab-separated values. Tabs, newlines and backslashes in texts are escaped as `\t`, `\n`,
+    /// `\r` and `\\`, as TSV has no quoting.
+    Tsv,
+    /// One JSON object per line, e.g. `{"text":"東京","count":3}`
+    JsonLines,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "jsonl" => Ok(Self::JsonLines),
+            _ => Err(format!("unknown format {s:?}: expected csv, tsv or jsonl")),
+        }
+    }
+}
+
+/// Writes the header, if the format has one, and a row for each text and its count.
+pub fn write_counts<W: io::Write, K: Display>(
+    out: &mut W,
+    format: OutputFormat,
+    counts: &[(K, usize)],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            writeln!(out, r#""text","count""#)?;
+            for (k, v) in counts {
+                writeln!(out, "{},{v}", quote_csv(&k.to_string()))?;
+            }
+        }
+        OutputFormat::Tsv => {
+            writeln!(out, "text\tcount")?;
+            for (k, v) in counts {
+                writeln!(out, "{}\t{v}", escape_tsv(&k.to_string()))?;
+            }
+        }
+        OutputFormat::JsonLines => {
+            for (k, v) in counts {
+                let row = serde_json::json!({ "text": k.to_string(), "count": v });
+                writeln!(out, "{row}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the header, if the format has one, and a row for each missing character with its
+/// code point, its count and the sample strings. In CSV and TSV, the samples are joined with
+/// `" / "` into a single field.
+pub fn write_missing_chars<W: io::Write>(
+    out: &mut W,
+    format: OutputFormat,
+    missing: &[MissingChar],
+) -> io::Result<()> {
+    let code_point = |c: char| format!("U+{:04X}", c as u32);
+    match format {
+        OutputFormat::Csv => {
+            writeln!(out, r#""text","code_point","count","samples""#)?;
+            for m in missing {
+                writeln!(
+                    out,
+                    r#"{},"{}",{},{}"#,
+                    quote_csv(&m.c.to_string()),
+                    code_point(m.c),
+                    m.count,
+                    quote_csv(&m.samples.join(" / "))
+                )?;
+            }
+        }
+        OutputFormat::Tsv => {
+            writeln!(out, "text\tcode_point\tcount\tsamples")?;
+            for m in missing {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    escape_tsv(&m.c.to_string()),
+                    code_point(m.c),
+                    m.count,
+                    escape_tsv(&m.samples.join(" / "))
+                )?;
+            }
+        }
+        OutputFormat::JsonLines => {
+            for m in missing {
+                let row = serde_json::json!({
+                    "text": m.c.to_string(),
+                    "code_point": code_point(m.c),
+                    "count": m.count,
+                    "samples": m.samples,
+                });
+                writeln!(out, "{row}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the header, if the format has one, and a row for each group of characters with the
+/// number of distinct characters and the sum of their counts. `key_name` is the name of the
+/// column of the keys, e.g. `range`.
+pub fn write_char_groups<W: io::Write, K: Display>(
+    out: &mut W,
+    format: OutputFormat,
+    key_name: &str,
+    groups: &[CharGroup<K>],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            writeln!(out, r#"{},"chars","count""#, quote_csv(key_name))?;
+            for g in groups {
+                let key = quote_csv(&g.key.to_string());
+                writeln!(out, "{key},{},{}", g.n_chars, g.count)?;
+            }
+        }
+        OutputFormat::Tsv => {
+            writeln!(out, "{}\tchars\tcount", escape_tsv(key_name))?;
+            for g in groups {
+                let key = escape_tsv(&g.key.to_string());
+                writeln!(out, "{key}\t{}\t{}", g.n_chars, g.count)?;
+            }
+        }
+        OutputFormat::JsonLines => {
+            for g in groups {
+                let row = serde_json::json!({
+                    key_name: g.key.to_string(),
+                    "chars": g.n_chars,
+                    "count": g.count,
+                });
+                writeln!(out, "{row}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quotes the field, doubling the quotes in it. Commas and line breaks need no escaping
+/// within quotes.
+fn quote_csv(field: &str) -> String {
+    format!(r#""{}""#, field.replace('"', r#""""#))
+}
+
+fn escape_tsv(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => result.push_str(r"\\"),
+            '\t' => result.push_str(r"\t"),
+            '\n' => result.push_str(r"\n"),
+            '\r' => result.push_str(r"\r"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: OutputFormat, counts: &[(&str, usize)]) -> String {
+        let mut out = vec![];
+        write_counts(&mut out, format, counts).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let counts = [("東京", 3), (r#"say "hi", ok"#, 2), ("two\nlines", 1)];
+        assert_eq!(
+            write(OutputFormat::Csv, &counts),
+            "\"text\",\"count\"\n\"東京\",3\n\"say \"\"hi\"\", ok\",2\n\"two\nlines\",1\n"
+        );
+    }
+
+    #[test]
+    fn test_write_tsv() {
+        let counts = [("東京", 3), ("a\tb\\c", 2), ("two\r\nlines", 1)];
+        assert_eq!(
+            write(OutputFormat::Tsv, &counts),
+            "text\tcount\n東京\t3\na\\tb\\\\c\t2\ntwo\\r\\nlines\t1\n"
+        );
+    }
+
+    #[test]
+    fn test_write_json_lines() {
+        let counts = [("東京", 3), ("\"\n", 1)];
+        let output = write(OutputFormat::JsonLines, &counts);
+        let rows: Vec<serde_json::Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({ "text": "東京", "count": 3 }),
+                serde_json::json!({ "text": "\"\n", "count": 1 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_missing_chars() {
+        let missing = [MissingChar {
+            c: '髙',
+            count: 5,
+            samples: vec!["髙島屋".to_string(), "\"髙\"".to_string()],
+        }];
+        let write = |format| {
+            let mut out = vec![];
+            write_missing_chars(&mut out, format, &missing).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            write(OutputFormat::Csv),
+            "\"text\",\"code_point\",\"count\",\"samples\"\n\"髙\",\"U+9AD9\",5,\"髙島屋 / \"\"髙\"\"\"\n"
+        );
+        assert_eq!(
+            write(OutputFormat::Tsv),
+            "text\tcode_point\tcount\tsamples\n髙\tU+9AD9\t5\t髙島屋 / \"髙\"\n"
+        );
+        let row: serde_json::Value =
+            serde_json::from_str(write(OutputFormat::JsonLines).trim_end()).unwrap();
+        assert_eq!(
+            row,
+            serde_json::json!({
+                "text": "髙",
+                "code_point": "U+9AD9",
+                "count": 5,
+                "samples": ["髙島屋", "\"髙\""],
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_char_groups() {
+        let groups = [
+            CharGroup {
+                key: "0-255.pbf",
+                n_chars: 2,
+                count: 10,
+            },
+            CharGroup {
+                key: "a\"b",
+                n_chars: 1,
+                count: 3,
+            },
+        ];
+        let write = |format| {
+            let mut out = vec![];
+            write_char_groups(&mut out, format, "range", &groups).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            write(OutputFormat::Csv),
+            "\"range\",\"chars\",\"count\"\n\"0-255.pbf\",2,10\n\"a\"\"b\",1,3\n"
+        );
+        assert_eq!(
+            write(OutputFormat::Tsv),
+            "range\tchars\tcount\n0-255.pbf\t2\t10\na\"b\t1\t3\n"
+        );
+        let rows: Vec<serde_json::Value> = write(OutputFormat::JsonLines)
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(
+            rows[0],
+            serde_json::json!({ "range": "0-255.pbf", "chars": 2, "count": 10 })
+        );
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("csv".parse(), Ok(OutputFormat::Csv));
+        assert_eq!("tsv".parse(), Ok(OutputFormat::Tsv));
+        assert_eq!("jsonl".parse(), Ok(OutputFormat::JsonLines));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
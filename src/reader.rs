@@ -0,0 +1,60 @@
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
+
+#[cfg(feature = "http")]
+mod http;
+
+#[cfg(feature = "http")]
+pub use http::HttpReader;
+
+/// Random access to the bytes of a PMTiles archive.
+///
+/// This is implemented for every `Read + Seek` type, e.g. [`std::fs::File`] or
+/// [`std::io::Cursor`] over an in-memory buffer. [`MmapReader`] reads a memory-mapped file
+/// without copying, and, with the `http` feature, [`HttpReader`] reads remote archives.
+pub trait RangeReader {
+    /// Reads exactly `length` bytes starting at `offset`.
+    fn read_range(&mut self, offset: u64, length: usize) -> std::io::Result<Cow<'_, [u8]>>;
+}
+
+impl<R: Read + Seek> RangeReader for R {
+    fn read_range(&mut self, offset: u64, length: usize) -> std::io::Result<Cow<'_, [u8]>> {
+        // the length may come from a corrupt archive, so the buffer only grows as far as the
+        // data actually goes
+        let mut buf = vec![];
+        self.seek(SeekFrom::Start(offset))?;
+        self.by_ref().take(length as u64).read_to_end(&mut buf)?;
+        if buf.len() < length {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Cow::Owned(buf))
+    }
+}
+
+/// Reads a memory-mapped file. Ranges are handed out as slices of the mapping, so reading
+/// involves neither syscalls nor copying.
+pub struct MmapReader {
+    mmap: memmap2::Mmap,
+}
+
+impl MmapReader {
+    /// Maps the file into memory.
+    ///
+    /// As with any memory-mapped file, the file must not be modified while it is mapped.
+    pub fn open<T: AsRef<std::path::Path>>(path: T) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the archives are read-only inputs; see the doc comment above.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(Self { mmap })
+    }
+}
+
+impl RangeReader for MmapReader {
+    fn read_range(&mut self, offset: u64, length: usize) -> std::io::Result<Cow<'_, [u8]>> {
+        usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.mmap.get(start..start.checked_add(length)?))
+            .map(Cow::Borrowed)
+            .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+    }
+}
@@ -0,0 +1,158 @@
+use std::borrow::Cow;
+use std::io::Read as _;
+
+use crate::{Error, PMTilesFile, RangeReader};
+
+/// The number of directories [`PMTilesFile::open_url`] keeps in memory.
+const DIRECTORY_CACHE_CAPACITY: usize = 256;
+
+/// Reads a remote archive with HTTP Range requests, so that only the needed parts of it are
+/// downloaded.
+pub struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl HttpReader {
+    pub fn new<T: Into<String>>(url: T) -> Self {
+        Self {
+            agent: ureq::Agent::new(),
+            url: url.into(),
+        }
+    }
+}
+
+impl RangeReader for HttpReader {
+    fn read_range(&mut self, offset: u64, length: usize) -> std::io::Result<Cow<'_, [u8]>> {
+        if length == 0 {
+            return Ok(Cow::Borrowed(&[]));
+        }
+
+        // offsets and lengths from a corrupt archive may overflow
+        let last = offset.checked_add(length as u64 - 1).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("range {offset}+{length} overflows"),
+            )
+        })?;
+        let range = format!("bytes={offset}-{last}");
+        let response = self
+            .agent
+            .get(&self.url)
+            .set("Range", &range)
+            .call()
+            .map_err(std::io::Error::other)?;
+
+        // A server that ignores Range would send the whole archive, which can be several GBs
+        if response.status() != 206 {
+            return Err(std::io::Error::other(format!(
+                "{} doesn't support range requests (status: {})",
+                self.url,
+                response.status()
+            )));
+        }
+
+        // the buffer grows with the data received rather than the untrusted length
+        let mut buf = vec![];
+        response
+            .into_reader()
+            .take(length as u64)
+            .read_to_end(&mut buf)?;
+        if buf.len() < length {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Cow::Owned(buf))
+    }
+}
+
+impl PMTilesFile<HttpReader> {
+    /// Opens a remote archive. Directories are cached as every read is a request.
+    pub fn open_url(url: &str) -> Result<Self, Error> {
+        Ok(Self::from_reader(HttpReader::new(url))?.with_directory_cache(DIRECTORY_CACHE_CAPACITY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    const FIXTURE: &[u8] = include_bytes!("../test/test_fixture_1.pmtiles");
+
+    /// Serves the fixture on a random port and returns its URL and the number of requests.
+    fn serve_fixture() -> (String, Arc<AtomicUsize>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test.pmtiles", server.server_addr());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .map(|h| h.value.to_string());
+                let (start, end) = range
+                    .as_deref()
+                    .and_then(|r| r.strip_prefix("bytes="))
+                    .and_then(|r| r.split_once('-'))
+                    .map(|(s, e)| (s.parse::<usize>().unwrap(), e.parse::<usize>().unwrap()))
+                    .unwrap();
+
+                let response = if start < FIXTURE.len() {
+                    let end = end.min(FIXTURE.len() - 1);
+                    tiny_http::Response::from_data(&FIXTURE[start..=end]).with_status_code(206)
+                } else {
+                    tiny_http::Response::from_data(vec![]).with_status_code(416)
+                };
+                request.respond(response).unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[test]
+    fn test_read_over_http() {
+        let (url, requests) = serve_fixture();
+
+        let mut file = PMTilesFile::open_url(&url).unwrap();
+        assert_eq!(file.parse_header().tile_data_offset, 399);
+        assert!(file.parse_metadata().unwrap().starts_with('{'));
+
+        let entry = file.find_tile_entry(0).unwrap().unwrap();
+        let tile = file
+            .parse_tile(entry.offset, entry.length as usize)
+            .unwrap();
+        assert_eq!(tile.layers[0].name, "test_fixture_1pmtiles");
+
+        // header, metadata, root directory, and tile
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // the root directory comes from the cache
+        file.parse_root_directory().unwrap();
+        assert!(file.find_tile_entry(1).unwrap().is_none());
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_read_beyond_end() {
+        let (url, _) = serve_fixture();
+
+        let mut reader = HttpReader::new(url);
+        assert!(reader.read_range(400, 100).is_err());
+        assert!(reader.read_range(1000, 10).is_err());
+
+        // fails before sending a request
+        let error = reader.read_range(u64::MAX, 2).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,619 @@
+//! Counting the strings in the tiles of an archive, which the `text` and `char` subcommands
+//! are built on.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    Error, PMTilesFile, RangeReader, bbox::BBox, directory::PMTilesEntry, mvt, tile_id,
+    traverse::DirectoryItem,
+};
+
+/// Tile data shared by one or more tiles, which happens with run-length encoded entries and
+/// deduplicated archives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileContent {
+    /// The entry of the first tile referencing the data
+    pub entry: PMTilesEntry,
+    /// The number of tiles referencing the data
+    pub n_tiles: usize,
+}
+
+/// Returns the data (i.e. the offset and the length) that the tiles in the zoom range and the
+/// bounding box of the options point to, in the order of first appearance, reading the leaf
+/// directories at any depth. Only the first `limit` entries of the root directory are used.
+///
+/// The entries are grouped while traversing, so only one entry per distinct data is kept in
+/// memory. `on_entry` is called for each entry visited, e.g. to show the progress while the
+/// directories are read.
+pub fn collect_tile_contents<R: RangeReader>(
+    file: &mut PMTilesFile<R>,
+    limit: Option<usize>,
+    options: &ScanOptions,
+    on_entry: &dyn Fn(&DirectoryItem),
+) -> Result<Vec<TileContent>, Error> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut result: Vec<TileContent> = vec![];
+    let mut index: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut n_root_entries = 0;
+
+    let bbox = match &options.bbox {
+        Some(bbox) => match bbox.clip_to_header(file.parse_header()) {
+            Some(bbox) => Some(bbox),
+            None => return Ok(result),
+        },
+        None => None,
+    };
+
+    let tile_ids = tile_id::tile_id_range(options.min_zoom, options.max_zoom);
+    for item in file.traverse()?.with_tile_ids(tile_ids) {
+        let item = item?;
+        on_entry(&item);
+        if item.depth == 0 {
+            n_root_entries += 1;
+            if n_root_entries > limit {
+                break;
+            }
+        }
+
+        let e = item.entry;
+        if !e.is_tile || bbox.is_some_and(|b| !b.intersects_tile_id(e.tile_id)) {
+            continue;
+        }
+        match index.get(&(e.offset, e.length)) {
+            Some(&i) => result[i].n_tiles += 1,
+            None => {
+                index.insert((e.offset, e.length), result.len());
+                result.push(TileContent {
+                    entry: e,
+                    n_tiles: 1,
+                });
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// The number of threads. The contents are split into this number of contiguous chunks,
+    /// each of which is read by a thread with its own reader.
+    pub jobs: usize,
+    /// If true, strings in data shared by multiple tiles are counted once rather than once
+    /// per tile.
+    pub count_shared_once: bool,
+    /// If true, a string is counted once per feature referencing it. Otherwise, it's counted
+    /// once per layer, as values are deduplicated within a layer.
+    pub per_feature: bool,
+    /// Glob patterns (`*` and `?`) of the layers to count. Empty means all the layers.
+    pub layers: Vec<String>,
+    /// Glob patterns of the layers not to count, applied after `layers`.
+    pub exclude_layers: Vec<String>,
+    /// The keys whose values are counted. Empty means all the string values, including those
+    /// no feature references.
+    pub keys: Vec<String>,
+    /// The zoom range of the tiles to collect with [`collect_tile_contents`]
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// If set, [`collect_tile_contents`] only collects the tiles intersecting the box.
+    pub bbox: Option<BBox>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            jobs: 1,
+            count_shared_once: false,
+            per_feature: false,
+            layers: vec![],
+            exclude_layers: vec![],
+            keys: vec![],
+            min_zoom: 0,
+            max_zoom: tile_id::MAX_ZOOM,
+            bbox: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn is_layer_selected(&self, name: &str) -> bool {
+        (self.layers.is_empty() || self.layers.iter().any(|p| glob_match(p, name)))
+            && !self.exclude_layers.iter().any(|p| glob_match(p, name))
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any sequence of characters and `?` any
+/// single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // the position of the last `*` and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        // `*` first, as it may also appear in the text literally
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if let Some((sp, st)) = star {
+            // let the `*` match one more character
+            star = Some((sp, st + 1));
+            p = sp + 1;
+            t = st + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Counts the string values in the tiles. Each content is decoded only once even if it's
+/// shared by multiple tiles.
+///
+/// `open` is called to get a reader for each thread. `on_tile` is called after each content is
+/// processed.
+pub fn count_strings<R, F>(
+    open: F,
+    contents: &[TileContent],
+    options: &ScanOptions,
+    on_tile: &(dyn Fn(&TileContent) + Sync),
+) -> Result<HashMap<String, usize>, Error>
+where
+    R: RangeReader,
+    F: Fn() -> Result<PMTilesFile<R>, Error> + Sync,
+{
+    if contents.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let chunk_size = contents.len().div_ceil(options.jobs.max(1));
+
+    let results: Vec<Result<HashMap<String, usize>, Error>> = std::thread::scope(|s| {
+        let handles: Vec<_> = contents
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let open = &open;
+                s.spawn(move || {
+                    let mut file = open()?;
+                    let mut counts = HashMap::new();
+                    for c in chunk {
+                        let tile = file.parse_tile(c.entry.offset, c.entry.length as usize)?;
+                        let weight = if options.count_shared_once {
+                            1
+                        } else {
+                            c.n_tiles
+                        };
+                        count_tile_strings(tile, weight, options, &mut counts);
+                        on_tile(c);
+                    }
+                    Ok(counts)
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut merged: HashMap<String, usize> = HashMap::new();
+    for counts in results {
+        for (k, v) in counts? {
+            *merged.entry(k).or_insert(0) += v;
+        }
+    }
+
+    Ok(merged)
+}
+
+fn count_tile_strings(
+    tile: mvt::Tile,
+    weight: usize,
+    options: &ScanOptions,
+    counts: &mut HashMap<String, usize>,
+) {
+    for l in tile.layers {
+        if !options.is_layer_selected(&l.name) {
+            continue;
+        }
+
+        // tags are pairs of indices into keys and values
+        let is_tag_selected = |kv: &&[u32]| {
+            options.keys.is_empty()
+                || l.keys
+                    .get(kv[0] as usize)
+                    .is_some_and(|k| options.keys.contains(k))
+        };
+
+        if !options.per_feature {
+            // without keys to select, values are counted whether referenced or not
+            let mut is_referenced = vec![options.keys.is_empty(); l.values.len()];
+            if !options.keys.is_empty() {
+                for f in &l.features {
+                    for kv in f.tags.chunks_exact(2).filter(is_tag_selected) {
+                        if let Some(r) = is_referenced.get_mut(kv[1] as usize) {
+                            *r = true;
+                        }
+                    }
+                }
+            }
+
+            for (v, referenced) in l.values.into_iter().zip(is_referenced) {
+                if let (Some(s), true) = (v.string_value, referenced) {
+                    *counts.entry(s).or_insert(0) += weight;
+                }
+            }
+            continue;
+        }
+
+        let mut value_indices = vec![];
+        for f in &l.features {
+            value_indices.clear();
+            value_indices.extend(
+                f.tags
+                    .chunks_exact(2)
+                    .filter(is_tag_selected)
+                    .map(|kv| kv[1] as usize),
+            );
+            // a feature counts once even if it has the same value for multiple keys
+            value_indices.sort_unstable();
+            value_indices.dedup();
+
+            for &i in &value_indices {
+                if let Some(s) = l.values.get(i).and_then(|v| v.string_value.as_ref()) {
+                    match counts.get_mut(s) {
+                        Some(count) => *count += weight,
+                        None => {
+                            counts.insert(s.clone(), weight);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Breaks down the counts of strings into the counts of characters.
+pub fn count_chars(strings: &HashMap<String, usize>) -> HashMap<char, usize> {
+    let mut result = HashMap::new();
+    for (s, count) in strings {
+        for c in s.chars() {
+            *result.entry(c).or_insert(0) += count;
+        }
+    }
+    result
+}
+
+/// The number of distinct characters in a group and the sum of their counts, see
+/// [`group_chars`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharGroup<K> {
+    pub key: K,
+    pub n_chars: usize,
+    pub count: usize,
+}
+
+/// Groups the counts of characters by the key each character maps to, in the order of the
+/// keys. Keys without any of the characters are omitted.
+pub fn group_chars<K: Ord + Copy>(
+    counts: &[(char, usize)],
+    key: impl Fn(char) -> K,
+) -> Vec<CharGroup<K>> {
+    let mut groups: BTreeMap<K, CharGroup<K>> = BTreeMap::new();
+    for &(c, count) in counts {
+        let key = key(c);
+        let group = groups.entry(key).or_insert(CharGroup {
+            key,
+            n_chars: 0,
+            count: 0,
+        });
+        group.n_chars += 1;
+        group.count += count;
+    }
+    groups.into_values().collect()
+}
+
+/// Sorts the counts in descending order. Ties are ordered by the key so that the result is
+/// deterministic.
+pub fn sort_counts<K: Ord>(counts: HashMap<K, usize>) -> Vec<(K, usize)> {
+    let mut sorted: Vec<(K, usize)> = counts.into_iter().collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{build_archive, make_tile};
+
+    fn test_archive() -> Vec<u8> {
+        let tiles: Vec<_> = (0..16)
+            .map(|i| {
+                let label = format!("label{}", i % 3);
+                let tile = make_tile(&[
+                    ("Anno", &[&[("vt_text", &label)], &[("vt_text", "共通")]]),
+                    ("RailCL", &[&[("vt_code", "5.5m")]]),
+                ]);
+                ((2, i % 4, i / 4), tile)
+            })
+            .collect();
+        build_archive(&tiles, 5)
+    }
+
+    /// Collects the tile contents of the archive and counts the strings in them.
+    fn count(data: &[u8], options: &ScanOptions) -> HashMap<String, usize> {
+        let open = || PMTilesFile::from_reader(Cursor::new(data));
+        let contents = collect_tile_contents(&mut open().unwrap(), None, options, &|_| {}).unwrap();
+        count_strings(open, &contents, options, &|_| {}).unwrap()
+    }
+
+    #[test]
+    fn test_count_strings() {
+        let counts = count(&test_archive(), &ScanOptions::default());
+        assert_eq!(counts["共通"], 16);
+        assert_eq!(counts["5.5m"], 16);
+        assert_eq!(counts["label0"], 6);
+        assert_eq!(counts["label1"], 5);
+        assert_eq!(counts["label2"], 5);
+
+        let chars = count_chars(&counts);
+        assert_eq!(chars[&'共'], 16);
+        assert_eq!(chars[&'l'], 32);
+        assert_eq!(chars[&'0'], 6);
+    }
+
+    #[test]
+    fn test_count_strings_parallel() {
+        let data = test_archive();
+        let open = || PMTilesFile::from_reader(Cursor::new(data.as_slice()));
+        let contents =
+            collect_tile_contents(&mut open().unwrap(), None, &ScanOptions::default(), &|_| {})
+                .unwrap();
+
+        let expected = count(&data, &ScanOptions::default());
+        for jobs in [2, 3, 16, 100] {
+            let options = ScanOptions {
+                jobs,
+                ..Default::default()
+            };
+            let n_decoded = std::sync::atomic::AtomicUsize::new(0);
+            let counts = count_strings(open, &contents, &options, &|_| {
+                n_decoded.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            })
+            .unwrap();
+            assert_eq!(counts, expected);
+            assert_eq!(n_decoded.into_inner(), 3);
+        }
+    }
+
+    #[test]
+    fn test_count_shared_once() {
+        let options = ScanOptions {
+            count_shared_once: true,
+            ..Default::default()
+        };
+        let counts = count(&test_archive(), &options);
+        assert_eq!(counts["共通"], 3);
+        assert_eq!(counts["label0"], 1);
+        assert_eq!(counts["label1"], 1);
+        assert_eq!(counts["label2"], 1);
+    }
+
+    #[test]
+    fn test_count_per_feature() {
+        let tile = make_tile(&[(
+            "Anno",
+            &[
+                &[("vt_text", "A")],
+                &[("vt_text", "A"), ("vt_code", "1")],
+                &[("vt_text", "A"), ("vt_alt", "A")],
+                &[("vt_text", "B")],
+            ],
+        )]);
+        let data = build_archive(&[((0, 0, 0), tile)], 10);
+
+        let counts = count(&data, &ScanOptions::default());
+        assert_eq!(
+            counts,
+            HashMap::from([("A".into(), 1), ("B".into(), 1), ("1".into(), 1)])
+        );
+
+        let options = ScanOptions {
+            per_feature: true,
+            ..Default::default()
+        };
+        let counts = count(&data, &options);
+        assert_eq!(
+            counts,
+            HashMap::from([("A".into(), 3), ("B".into(), 1), ("1".into(), 1)])
+        );
+    }
+
+    #[test]
+    fn test_count_strings_layers() {
+        let data = test_archive();
+
+        let options = ScanOptions {
+            layers: vec!["Anno".to_string()],
+            ..Default::default()
+        };
+        let counts = count(&data, &options);
+        assert_eq!(counts["共通"], 16);
+        assert!(!counts.contains_key("5.5m"));
+
+        let options = ScanOptions {
+            layers: vec!["*".to_string()],
+            exclude_layers: vec!["A?no".to_string()],
+            ..Default::default()
+        };
+        let counts = count(&data, &options);
+        assert_eq!(counts.keys().collect::<Vec<_>>(), vec!["5.5m"]);
+    }
+
+    #[test]
+    fn test_count_strings_keys() {
+        let tile = make_tile(&[(
+            "Anno",
+            &[
+                &[("vt_text", "東京"), ("vt_code", "LC")],
+                &[("vt_text", "東京")],
+                &[("vt_code", "東京")],
+            ],
+        )]);
+        let data = build_archive(&[((0, 0, 0), tile)], 5);
+
+        let options = ScanOptions {
+            keys: vec!["vt_text".to_string()],
+            ..Default::default()
+        };
+        let counts = count(&data, &options);
+        assert_eq!(counts, HashMap::from([("東京".to_string(), 1)]));
+
+        let options = ScanOptions {
+            per_feature: true,
+            ..options
+        };
+        let counts = count(&data, &options);
+        assert_eq!(counts, HashMap::from([("東京".to_string(), 2)]));
+
+        let options = ScanOptions {
+            keys: vec!["vt_code".to_string()],
+            ..options
+        };
+        let counts = count(&data, &options);
+        assert_eq!(counts["LC"], 1);
+        assert_eq!(counts["東京"], 1);
+    }
+
+    #[test]
+    fn test_collect_tile_contents_zoom() {
+        let data = test_archive();
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        let options = ScanOptions {
+            min_zoom: 1,
+            max_zoom: 2,
+            ..Default::default()
+        };
+        let contents = collect_tile_contents(&mut file, None, &options, &|_| {}).unwrap();
+        assert_eq!(contents.iter().map(|c| c.n_tiles).sum::<usize>(), 16);
+
+        let options = ScanOptions {
+            min_zoom: 3,
+            ..Default::default()
+        };
+        let contents = collect_tile_contents(&mut file, None, &options, &|_| {}).unwrap();
+        assert!(contents.is_empty());
+    }
+
+    #[test]
+    fn test_collect_tile_contents_bbox() {
+        // every tile has distinct data
+        let tiles: Vec<_> = (0..16)
+            .map(|i| {
+                let label = format!("label{i}");
+                (
+                    (2, i % 4, i / 4),
+                    make_tile(&[("Anno", &[&[("vt_text", &label)]])]),
+                )
+            })
+            .collect();
+        let data = build_archive(&tiles, 5);
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        // the north-east quarter, i.e. x 2 to 3 and y 0 to 1 at z2
+        let options = ScanOptions {
+            bbox: Some("1,1,179,84".parse().unwrap()),
+            ..Default::default()
+        };
+        let contents = collect_tile_contents(&mut file, None, &options, &|_| {}).unwrap();
+        let mut zxy: Vec<_> = contents
+            .iter()
+            .map(|c| tile_id::tile_id_to_zxy(c.entry.tile_id).unwrap())
+            .collect();
+        zxy.sort();
+        assert_eq!(zxy, vec![(2, 2, 0), (2, 2, 1), (2, 3, 0), (2, 3, 1)]);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Anno", "Anno"));
+        assert!(!glob_match("Anno", "AnnoX"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("Rail*", "RailCL"));
+        assert!(glob_match("*CL", "RailCL"));
+        assert!(glob_match("W*A", "WStrA"));
+        assert!(!glob_match("W*A", "WStrL"));
+        assert!(glob_match("*a*a*", "banana"));
+        assert!(glob_match("?dmArea", "AdmArea"));
+        assert!(!glob_match("?", "ab"));
+        assert!(glob_match("注*", "注記"));
+        // literal `*` in the text
+        assert!(glob_match("*", "*x"));
+        assert!(glob_match("a*", "a*b"));
+        assert!(glob_match("a*b", "a*b"));
+        assert!(!glob_match("a*c", "a*b"));
+    }
+
+    #[test]
+    fn test_collect_tile_contents() {
+        let data = test_archive();
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        let n_tiles = std::cell::Cell::new(0);
+        let contents = collect_tile_contents(&mut file, None, &ScanOptions::default(), &|item| {
+            if item.entry.is_tile {
+                n_tiles.set(n_tiles.get() + 1);
+            }
+        })
+        .unwrap();
+        assert_eq!(n_tiles.get(), 16);
+
+        // in the order of first appearance, with the entry of the first tile
+        assert_eq!(contents.len(), 3);
+        assert!(contents.is_sorted_by_key(|c| c.entry.tile_id));
+        let entries: Vec<_> = file.tile_entries().unwrap().map(|e| e.unwrap()).collect();
+        for c in &contents {
+            let first = entries
+                .iter()
+                .find(|e| (e.offset, e.length) == (c.entry.offset, c.entry.length))
+                .unwrap();
+            assert_eq!(&c.entry, first);
+        }
+
+        let mut n_tiles: Vec<usize> = contents.iter().map(|c| c.n_tiles).collect();
+        n_tiles.sort();
+        assert_eq!(n_tiles, vec![5, 5, 6]);
+    }
+
+    #[test]
+    fn test_group_chars() {
+        let counts = [('b', 1), ('C', 2), ('a', 3), ('B', 4)];
+        assert_eq!(
+            group_chars(&counts, |c| c.is_uppercase()),
+            vec![
+                CharGroup {
+                    key: false,
+                    n_chars: 2,
+                    count: 4
+                },
+                CharGroup {
+                    key: true,
+                    n_chars: 2,
+                    count: 6
+                },
+            ]
+        );
+        assert!(group_chars(&[], |c| c).is_empty());
+    }
+
+    #[test]
+    fn test_sort_counts() {
+        let counts = HashMap::from([("b", 1), ("c", 2), ("a", 1)]);
+        assert_eq!(sort_counts(counts), vec![("c", 2), ("a", 1), ("b", 1)]);
+    }
+}
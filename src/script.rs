@@ -0,0 +1,262 @@
+//! Grouping characters by script and Unicode block, to estimate how large a font covering them
+//! has to be.
+
+use std::fmt;
+
+use crate::{
+    scan::{CharGroup, group_chars},
+    unicode_blocks::BLOCKS,
+};
+
+/// Where a Han character is in JIS X 0208, which most Japanese fonts cover at least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JisLevel {
+    /// Rows 16 to 47 (第一水準)
+    Level1,
+    /// Rows 48 to 84 (第二水準)
+    Level2,
+    /// The non-kanji rows 1 to 8, which have `々` and `〇`
+    NonKanji,
+    /// Not in JIS X 0208, e.g. JIS X 0213 or the IBM extensions
+    Outside,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CharCategory {
+    /// CJK Unified Ideographs including the extensions, CJK Compatibility Ideographs, and the
+    /// ideographic marks such as `々` and `〇`
+    Han(JisLevel),
+    Hiragana,
+    /// Katakana and Katakana Phonetic Extensions
+    Katakana,
+    /// Letters up to Latin Extended-B, and Latin Extended Additional
+    Latin,
+    /// ASCII digits
+    Digit,
+    /// Halfwidth and Fullwidth Forms, e.g. `Ａ`, `１` and halfwidth katakana
+    Fullwidth,
+    /// Punctuation and symbols, e.g. `-`, `、` and `〒`
+    Symbol,
+    /// Letters of the other scripts, whitespace and control characters
+    Other,
+}
+
+impl fmt::Display for CharCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Han(JisLevel::Level1) => "Han (JIS level 1)",
+            Self::Han(JisLevel::Level2) => "Han (JIS level 2)",
+            Self::Han(JisLevel::NonKanji) => "Han (JIS non-kanji rows)",
+            Self::Han(JisLevel::Outside) => "Han (outside JIS X 0208)",
+            Self::Hiragana => "Hiragana",
+            Self::Katakana => "Katakana",
+            Self::Latin => "Latin",
+            Self::Digit => "Digit",
+            Self::Fullwidth => "Fullwidth",
+            Self::Symbol => "Symbol",
+            Self::Other => "Other",
+        };
+        f.write_str(name)
+    }
+}
+
+pub fn classify(c: char) -> CharCategory {
+    match c {
+        // 々, 〇 and 〻 are in CJK Symbols and Punctuation but have Script=Han
+        '\u{3005}'
+        | '\u{3007}'
+        | '\u{303B}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{3FFFF}' => CharCategory::Han(jis_level(c)),
+        '\u{3040}'..='\u{309F}' => CharCategory::Hiragana,
+        '\u{30A0}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' => CharCategory::Katakana,
+        '\u{FF00}'..='\u{FFEF}' => CharCategory::Fullwidth,
+        '0'..='9' => CharCategory::Digit,
+        '\u{0}'..='\u{24F}' | '\u{1E00}'..='\u{1EFF}' if c.is_alphabetic() => CharCategory::Latin,
+        c if c.is_alphanumeric() || c.is_whitespace() || c.is_control() => CharCategory::Other,
+        _ => CharCategory::Symbol,
+    }
+}
+
+/// Looks the character up in JIS X 0208 by encoding it in EUC-JP, where the first byte of a
+/// character in row N is 0xA0 + N.
+pub fn jis_level(c: char) -> JisLevel {
+    let mut buf = [0; 4];
+    let (bytes, _, had_errors) = encoding_rs::EUC_JP.encode(c.encode_utf8(&mut buf));
+    match &bytes[..] {
+        [0xB0..=0xCF, _] if !had_errors => JisLevel::Level1,
+        [0xD0..=0xF4, _] if !had_errors => JisLevel::Level2,
+        [0xA1..=0xA8, _] if !had_errors => JisLevel::NonKanji,
+        _ => JisLevel::Outside,
+    }
+}
+
+/// Groups the counts of characters by category, in the order of [`CharCategory`].
+pub fn summarize(counts: &[(char, usize)]) -> Vec<CharGroup<CharCategory>> {
+    group_chars(counts, classify)
+}
+
+/// A Unicode block, e.g. CJK Unified Ideographs (U+4E00 to U+9FFF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UnicodeBlock {
+    pub start: u32,
+    pub end: u32,
+    pub name: &'static str,
+}
+
+impl UnicodeBlock {
+    /// The code points not assigned to any block, ordered after all the blocks
+    pub const NONE: Self = Self {
+        start: u32::MAX,
+        end: u32::MAX,
+        name: "No_Block",
+    };
+
+    pub fn of(c: char) -> Self {
+        let cp = c as u32;
+        // the last block starting at or before the code point
+        let i = BLOCKS.partition_point(|&(start, _, _)| start <= cp);
+        match i.checked_sub(1).map(|i| BLOCKS[i]) {
+            Some((start, end, name)) if cp <= end => Self { start, end, name },
+            _ => Self::NONE,
+        }
+    }
+}
+
+impl fmt::Display for UnicodeBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+/// Groups the counts of characters by Unicode block, in code point order.
+pub fn summarize_blocks(counts: &[(char, usize)]) -> Vec<CharGroup<UnicodeBlock>> {
+    group_chars(counts, UnicodeBlock::of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify('東'), CharCategory::Han(JisLevel::Level1));
+        assert_eq!(classify('亞'), CharCategory::Han(JisLevel::Level2));
+        assert_eq!(classify('髙'), CharCategory::Han(JisLevel::Outside));
+        assert_eq!(classify('𠮟'), CharCategory::Han(JisLevel::Outside));
+        assert_eq!(classify('々'), CharCategory::Han(JisLevel::NonKanji));
+        assert_eq!(classify('〇'), CharCategory::Han(JisLevel::NonKanji));
+        assert_eq!(classify('の'), CharCategory::Hiragana);
+        assert_eq!(classify('ー'), CharCategory::Katakana);
+        assert_eq!(classify('ㇷ'), CharCategory::Katakana);
+        assert_eq!(classify('A'), CharCategory::Latin);
+        assert_eq!(classify('é'), CharCategory::Latin);
+        assert_eq!(classify('5'), CharCategory::Digit);
+        assert_eq!(classify('１'), CharCategory::Fullwidth);
+        assert_eq!(classify('ｶ'), CharCategory::Fullwidth);
+        assert_eq!(classify('-'), CharCategory::Symbol);
+        assert_eq!(classify('、'), CharCategory::Symbol);
+        assert_eq!(classify('〒'), CharCategory::Symbol);
+        assert_eq!(classify('×'), CharCategory::Symbol);
+        assert_eq!(classify('한'), CharCategory::Other);
+        assert_eq!(classify(' '), CharCategory::Other);
+    }
+
+    #[test]
+    fn test_jis_level() {
+        // the first and the last characters of each level
+        assert_eq!(jis_level('亜'), JisLevel::Level1);
+        assert_eq!(jis_level('腕'), JisLevel::Level1);
+        assert_eq!(jis_level('弌'), JisLevel::Level2);
+        assert_eq!(jis_level('熙'), JisLevel::Level2);
+        // non-kanji rows
+        assert_eq!(jis_level('々'), JisLevel::NonKanji);
+        assert_eq!(jis_level('あ'), JisLevel::NonKanji);
+        // ASCII, which is not encoded as JIS X 0208
+        assert_eq!(jis_level('A'), JisLevel::Outside);
+    }
+
+    #[test]
+    fn test_unicode_block() {
+        let block = UnicodeBlock::of('東');
+        assert_eq!((block.start, block.end), (0x4E00, 0x9FFF));
+        assert_eq!(block.to_string(), "CJK Unified Ideographs");
+        assert_eq!(UnicodeBlock::of('\0').name, "Basic Latin");
+        assert_eq!(UnicodeBlock::of('々').name, "CJK Symbols and Punctuation");
+        assert_eq!(UnicodeBlock::of('ｶ').name, "Halfwidth and Fullwidth Forms");
+        assert_eq!(
+            UnicodeBlock::of('𠮟').name,
+            "CJK Unified Ideographs Extension B"
+        );
+        // unassigned to any block
+        assert_eq!(UnicodeBlock::of('\u{2FE0}'), UnicodeBlock::NONE);
+        assert_eq!(UnicodeBlock::of('\u{E0080}'), UnicodeBlock::NONE);
+    }
+
+    #[test]
+    fn test_summarize_blocks() {
+        let counts = [
+            ('東', 30),
+            ('の', 50),
+            ('京', 10),
+            ('ノ', 1),
+            ('\u{2FE0}', 2),
+        ];
+        let blocks: Vec<_> = summarize_blocks(&counts)
+            .into_iter()
+            .map(|g| (g.key.name, g.n_chars, g.count))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                ("Hiragana", 1, 50),
+                ("Katakana", 1, 1),
+                ("CJK Unified Ideographs", 2, 40),
+                ("No_Block", 1, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let counts = [
+            ('の', 50),
+            ('東', 30),
+            ('京', 10),
+            ('A', 5),
+            ('B', 5),
+            ('亞', 1),
+        ];
+        assert_eq!(
+            summarize(&counts),
+            vec![
+                CharGroup {
+                    key: CharCategory::Han(JisLevel::Level1),
+                    n_chars: 2,
+                    count: 40
+                },
+                CharGroup {
+                    key: CharCategory::Han(JisLevel::Level2),
+                    n_chars: 1,
+                    count: 1
+                },
+                CharGroup {
+                    key: CharCategory::Hiragana,
+                    n_chars: 1,
+                    count: 50
+                },
+                CharGroup {
+                    key: CharCategory::Latin,
+                    n_chars: 2,
+                    count: 10
+                },
+            ]
+        );
+        assert_eq!(
+            CharCategory::Han(JisLevel::Outside).to_string(),
+            "Han (outside JIS X 0208)"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Turning character counts into inputs for font subsetting tools such as `pyftsubset`.
+
+use std::{fmt, ops::RangeInclusive};
+
+use crate::scan::{CharGroup, group_chars};
+
+/// Selects the characters to keep from counts sorted in descending order (see
+/// [`crate::scan::sort_counts`]).
+///
+/// With `min_count`, only the characters counted at least that many times are kept. With
+/// `coverage` (a percentage), only the most frequent characters are kept until they account
+/// for that share of all the occurrences.
+///
+/// As the least frequent characters are left out, the result is a prefix of `sorted`.
+pub fn select_chars(
+    sorted: &[(char, usize)],
+    min_count: Option<usize>,
+    coverage: Option<f64>,
+) -> Vec<char> {
+    let total: usize = sorted.iter().map(|(_, count)| count).sum();
+    let target = coverage.map(|percent| total as f64 * percent / 100.0);
+
+    let mut result = vec![];
+    let mut covered = 0;
+    for &(c, count) in sorted {
+        if min_count.is_some_and(|min| count < min) {
+            break;
+        }
+        if target.is_some_and(|target| covered as f64 >= target) {
+            break;
+        }
+        result.push(c);
+        covered += count;
+    }
+
+    result
+}
+
+/// Merges the code points of the characters into sorted ranges.
+pub fn unicode_ranges(chars: &[char]) -> Vec<RangeInclusive<u32>> {
+    let mut code_points: Vec<u32> = chars.iter().map(|&c| c as u32).collect();
+    code_points.sort_unstable();
+    code_points.dedup();
+
+    let mut ranges: Vec<RangeInclusive<u32>> = vec![];
+    for cp in code_points {
+        match ranges.last_mut() {
+            Some(last) if *last.end() + 1 == cp => *last = *last.start()..=cp,
+            _ => ranges.push(cp..=cp),
+        }
+    }
+    ranges
+}
+
+/// Formats a range as `U+XXXX` or `U+XXXX-YYYY`, as `pyftsubset --unicodes` accepts.
+pub fn format_unicode_range(range: &RangeInclusive<u32>) -> String {
+    if range.start() == range.end() {
+        format!("U+{:04X}", range.start())
+    } else {
+        format!("U+{:04X}-{:04X}", range.start(), range.end())
+    }
+}
+
+/// Returns the characters in code point order, e.g. for `pyftsubset --text-file`.
+pub fn subset_text(chars: &[char]) -> String {
+    let mut chars = chars.to_vec();
+    chars.sort_unstable();
+    chars.dedup();
+    chars.into_iter().collect()
+}
+
+/// Where MapLibre loads the glyph of a character from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GlyphRange {
+    /// The 256 code points from `start`, a multiple of 256, loaded from `{start}-{end}.pbf`
+    Pbf { start: u32 },
+    /// Characters above U+FFFF, which MapLibre has no glyph PBFs for and can't render with them
+    Unsupported,
+}
+
+impl GlyphRange {
+    pub fn of(c: char) -> Self {
+        match c as u32 {
+            cp @ 0..=0xFFFF => Self::Pbf {
+                start: cp / 256 * 256,
+            },
+            _ => Self::Unsupported,
+        }
+    }
+
+    /// e.g. `0-255.pbf`, or `None` for unsupported characters
+    pub fn file_name(&self) -> Option<String> {
+        match self {
+            Self::Pbf { start } => Some(format!("{start}-{}.pbf", start + 255)),
+            Self::Unsupported => None,
+        }
+    }
+}
+
+impl fmt::Display for GlyphRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.file_name() {
+            Some(name) => f.write_str(&name),
+            None => f.write_str("unsupported"),
+        }
+    }
+}
+
+/// Groups the counts of characters into the glyph ranges containing them, in code point order,
+/// followed by the characters outside the ranges.
+pub fn glyph_ranges(counts: &[(char, usize)]) -> Vec<CharGroup<GlyphRange>> {
+    group_chars(counts, GlyphRange::of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTS: [(char, usize); 5] = [('の', 50), ('東', 30), ('京', 10), ('A', 5), ('B', 5)];
+
+    #[test]
+    fn test_select_chars() {
+        assert_eq!(select_chars(&COUNTS, None, None).len(), 5);
+        assert_eq!(
+            select_chars(&COUNTS, Some(10), None),
+            vec!['の', '東', '京']
+        );
+        // 50 + 30 = 80%
+        assert_eq!(select_chars(&COUNTS, None, Some(80.0)), vec!['の', '東']);
+        assert_eq!(
+            select_chars(&COUNTS, None, Some(81.0)),
+            vec!['の', '東', '京']
+        );
+        assert_eq!(select_chars(&COUNTS, None, Some(100.0)).len(), 5);
+        assert_eq!(select_chars(&COUNTS, Some(40), Some(90.0)), vec!['の']);
+        assert!(select_chars(&[], None, Some(50.0)).is_empty());
+    }
+
+    #[test]
+    fn test_unicode_ranges() {
+        let ranges = unicode_ranges(&['B', 'A', 'C', 'E', '京', '東', 'A']);
+        assert_eq!(
+            ranges,
+            vec![0x41..=0x43, 0x45..=0x45, 0x4EAC..=0x4EAC, 0x6771..=0x6771]
+        );
+
+        let formatted: Vec<String> = ranges.iter().map(format_unicode_range).collect();
+        assert_eq!(formatted, vec!["U+0041-0043", "U+0045", "U+4EAC", "U+6771"]);
+        assert_eq!(format_unicode_range(&(0x20000..=0x20001)), "U+20000-20001");
+    }
+
+    #[test]
+    fn test_glyph_ranges() {
+        let ranges = glyph_ranges(&COUNTS);
+        let group = |key, n_chars, count| CharGroup {
+            key,
+            n_chars,
+            count,
+        };
+        assert_eq!(
+            ranges,
+            vec![
+                group(GlyphRange::Pbf { start: 0 }, 2, 10),
+                group(GlyphRange::Pbf { start: 0x3000 }, 1, 50),
+                group(GlyphRange::Pbf { start: 0x4E00 }, 1, 10),
+                group(GlyphRange::Pbf { start: 0x6700 }, 1, 30),
+            ]
+        );
+        assert_eq!(ranges[0].key.to_string(), "0-255.pbf");
+        assert_eq!(ranges[1].key.to_string(), "12288-12543.pbf");
+    }
+
+    #[test]
+    fn test_glyph_ranges_supplementary() {
+        let ranges = glyph_ranges(&[('𠮟', 3), ('\u{FFFF}', 2), ('𠮷', 1)]);
+        assert_eq!(
+            ranges,
+            vec![
+                CharGroup {
+                    key: GlyphRange::Pbf { start: 0xFF00 },
+                    n_chars: 1,
+                    count: 2
+                },
+                CharGroup {
+                    key: GlyphRange::Unsupported,
+                    n_chars: 2,
+                    count: 4
+                },
+            ]
+        );
+        assert_eq!(ranges[0].key.to_string(), "65280-65535.pbf");
+        assert_eq!(ranges[1].key.file_name(), None);
+        assert_eq!(ranges[1].key.to_string(), "unsupported");
+    }
+
+    #[test]
+    fn test_subset_text() {
+        assert_eq!(subset_text(&['東', 'A', '京', 'A']), "A京東");
+    }
+}
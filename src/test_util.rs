@@ -0,0 +1,188 @@
+//! Builds small uncompressed archives in memory for tests.
+
+use prost::Message as _;
+
+use crate::{mvt, tile_id::zxy_to_tile_id};
+
+/// A layer of point features, each with string properties.
+pub(crate) type TestLayer<'a> = (&'a str, &'a [&'a [(&'a str, &'a str)]]);
+
+/// Creates a tile whose features are all points at (1, 1).
+pub(crate) fn make_tile(layers: &[TestLayer]) -> mvt::Tile {
+    let layers = layers
+        .iter()
+        .map(|(name, features)| {
+            let mut keys: Vec<String> = vec![];
+            let mut values: Vec<String> = vec![];
+            let features = features
+                .iter()
+                .map(|properties| {
+                    let mut tags = vec![];
+                    for (k, v) in properties.iter() {
+                        tags.push(index_of(&mut keys, k));
+                        tags.push(index_of(&mut values, v));
+                    }
+                    mvt::tile::Feature {
+                        tags,
+                        r#type: Some(mvt::tile::GeomType::Point as i32),
+                        geometry: vec![9, 2, 2],
+                        ..Default::default()
+                    }
+                })
+                .collect();
+
+            mvt::tile::Layer {
+                version: 2,
+                name: name.to_string(),
+                features,
+                keys,
+                values: values
+                    .into_iter()
+                    .map(|v| mvt::tile::Value {
+                        string_value: Some(v),
+                        ..Default::default()
+                    })
+                    .collect(),
+                extent: Some(4096),
+            }
+        })
+        .collect();
+
+    mvt::Tile { layers }
+}
+
+fn index_of(table: &mut Vec<String>, s: &str) -> u32 {
+    let i = table.iter().position(|x| x == s).unwrap_or_else(|| {
+        table.push(s.to_string());
+        table.len() - 1
+    });
+    i as u32
+}
+
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+/// Builds an archive of the tiles. Directories hold at most `leaf_size` entries; overflowing
+/// entries go to leaf directories, which are nested as deep as needed. Identical tiles share
+/// their data, and consecutive identical tiles are run-length encoded.
+pub(crate) fn build_archive(tiles: &[((u8, u32, u32), mvt::Tile)], leaf_size: usize) -> Vec<u8> {
+    let mut tiles: Vec<(u64, Vec<u8>)> = tiles
+        .iter()
+        .map(|((z, x, y), tile)| (zxy_to_tile_id(*z, *x, *y).unwrap(), tile.encode_to_vec()))
+        .collect();
+    tiles.sort_by_key(|(tile_id, _)| *tile_id);
+
+    let mut tile_data: Vec<u8> = vec![];
+    let mut contents: Vec<(Vec<u8>, u64)> = vec![];
+    let mut entries: Vec<Entry> = vec![];
+    for (tile_id, data) in tiles {
+        let offset = match contents.iter().find(|(c, _)| *c == data) {
+            Some((_, offset)) => *offset,
+            None => {
+                let offset = tile_data.len() as u64;
+                tile_data.extend(&data);
+                contents.push((data.clone(), offset));
+                offset
+            }
+        };
+
+        if let Some(last) = entries.last_mut()
+            && last.offset == offset
+            && last.tile_id + last.run_length == tile_id
+        {
+            last.run_length += 1;
+            continue;
+        }
+        entries.push(Entry {
+            tile_id,
+            offset,
+            length: data.len() as u64,
+            run_length: 1,
+        });
+    }
+
+    let mut leaf_dirs: Vec<u8> = vec![];
+    while entries.len() > leaf_size {
+        entries = entries
+            .chunks(leaf_size)
+            .map(|chunk| {
+                let dir = encode_directory(chunk);
+                let entry = Entry {
+                    tile_id: chunk[0].tile_id,
+                    offset: leaf_dirs.len() as u64,
+                    length: dir.len() as u64,
+                    run_length: 0,
+                };
+                leaf_dirs.extend(dir);
+                entry
+            })
+            .collect();
+    }
+    let root_dir = encode_directory(&entries);
+    let metadata = b"{}";
+
+    let root_offset = 127u64;
+    let metadata_offset = root_offset + root_dir.len() as u64;
+    let leaf_offset = metadata_offset + metadata.len() as u64;
+    let tile_offset = leaf_offset + leaf_dirs.len() as u64;
+
+    let mut out = b"PMTiles\x03".to_vec();
+    for v in [
+        root_offset,
+        root_dir.len() as u64,
+        metadata_offset,
+        metadata.len() as u64,
+        leaf_offset,
+        leaf_dirs.len() as u64,
+        tile_offset,
+        tile_data.len() as u64,
+        0, // the numbers of tiles are not used by the reader
+        0,
+        0,
+    ] {
+        out.extend(v.to_le_bytes());
+    }
+    // clustered, internal compression: none, tile compression: none, tile type: mvt
+    out.extend([0, 1, 1, 1]);
+    // zooms and positions
+    out.extend([0u8; 127 - 100]);
+    debug_assert_eq!(out.len(), 127);
+
+    out.extend(root_dir);
+    out.extend(metadata);
+    out.extend(leaf_dirs);
+    out.extend(tile_data);
+    out
+}
+
+fn encode_directory(entries: &[Entry]) -> Vec<u8> {
+    let mut out = vec![];
+    write_varint(&mut out, entries.len() as u64);
+    let mut last_tile_id = 0;
+    for e in entries {
+        write_varint(&mut out, e.tile_id - last_tile_id);
+        last_tile_id = e.tile_id;
+    }
+    for e in entries {
+        write_varint(&mut out, e.run_length);
+    }
+    for e in entries {
+        write_varint(&mut out, e.length);
+    }
+    for e in entries {
+        write_varint(&mut out, e.offset + 1);
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
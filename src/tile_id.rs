@@ -0,0 +1,171 @@
+// cf. https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md#tile-ids
+//
+// Tile IDs are assigned zoom by zoom; within a zoom level, tiles are ordered along
+// a Hilbert curve.
+
+use std::ops::Range;
+
+/// The maximum zoom level whose tile IDs fit in `u64`.
+pub const MAX_ZOOM: u8 = 31;
+
+/// Number of tiles on all the zoom levels below `z`, i.e. the first tile ID of `z`.
+fn zoom_offset(z: u8) -> u64 {
+    // computed in u128 so that z = MAX_ZOOM + 1 doesn't overflow
+    (((1u128 << (2 * z as u32)) - 1) / 3) as u64
+}
+
+/// The tile IDs of the zoom levels from `min_zoom` to `max_zoom` (inclusive). Zoom levels beyond
+/// [`MAX_ZOOM`] are ignored.
+pub fn tile_id_range(min_zoom: u8, max_zoom: u8) -> Range<u64> {
+    let max_zoom = max_zoom.min(MAX_ZOOM);
+    let min_zoom = min_zoom.min(max_zoom + 1);
+    zoom_offset(min_zoom)..zoom_offset(max_zoom + 1)
+}
+
+/// Converts z/x/y to the PMTiles tile ID. Returns `None` if the coordinates are out of range.
+pub fn zxy_to_tile_id(z: u8, x: u32, y: u32) -> Option<u64> {
+    if z > MAX_ZOOM {
+        return None;
+    }
+
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    if x >= n || y >= n {
+        return None;
+    }
+
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+        rotate(n, &mut x, &mut y, rx, ry);
+        s /= 2;
+    }
+
+    Some(zoom_offset(z) + d)
+}
+
+/// Converts the PMTiles tile ID to z/x/y. Returns `None` if the ID is beyond [`MAX_ZOOM`].
+pub fn tile_id_to_zxy(tile_id: u64) -> Option<(u8, u32, u32)> {
+    let mut z = 0;
+    let mut d = tile_id;
+    while d >= 1 << (2 * z as u32) {
+        d -= 1 << (2 * z as u32);
+        z += 1;
+        if z > MAX_ZOOM {
+            return None;
+        }
+    }
+
+    let n = 1u64 << z;
+
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        rotate(s, &mut x, &mut y, rx, ry);
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    Some((z, x as u32, y as u32))
+}
+
+fn rotate(n: u64, x: &mut u64, y: &mut u64, rx: u64, ry: u64) {
+    if ry == 0 {
+        if rx == 1 {
+            *x = n - 1 - *x;
+            *y = n - 1 - *y;
+        }
+        std::mem::swap(x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zxy_to_tile_id() {
+        assert_eq!(zxy_to_tile_id(0, 0, 0), Some(0));
+        assert_eq!(zxy_to_tile_id(1, 0, 0), Some(1));
+        assert_eq!(zxy_to_tile_id(1, 0, 1), Some(2));
+        assert_eq!(zxy_to_tile_id(1, 1, 1), Some(3));
+        assert_eq!(zxy_to_tile_id(1, 1, 0), Some(4));
+        assert_eq!(zxy_to_tile_id(2, 0, 0), Some(5));
+        assert_eq!(zxy_to_tile_id(12, 3423, 1763), Some(19078479));
+    }
+
+    #[test]
+    fn test_tile_id_to_zxy() {
+        assert_eq!(tile_id_to_zxy(0), Some((0, 0, 0)));
+        assert_eq!(tile_id_to_zxy(1), Some((1, 0, 0)));
+        assert_eq!(tile_id_to_zxy(2), Some((1, 0, 1)));
+        assert_eq!(tile_id_to_zxy(3), Some((1, 1, 1)));
+        assert_eq!(tile_id_to_zxy(4), Some((1, 1, 0)));
+        assert_eq!(tile_id_to_zxy(5), Some((2, 0, 0)));
+        assert_eq!(tile_id_to_zxy(19078479), Some((12, 3423, 1763)));
+        assert_eq!(
+            tile_id_to_zxy(zoom_offset(MAX_ZOOM) + (1 << 62) - 1),
+            Some((MAX_ZOOM, (1 << 31) - 1, 0))
+        );
+        assert_eq!(tile_id_to_zxy(zoom_offset(MAX_ZOOM) + (1 << 62)), None);
+        assert_eq!(tile_id_to_zxy(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // Since the IDs stay within the zoom level's range and the conversion is injective,
+        // this also shows every ID in the range is used.
+        for z in 0..=12 {
+            let ids = zoom_offset(z)..zoom_offset(z + 1);
+            for x in 0..(1 << z) {
+                for y in 0..(1 << z) {
+                    let tile_id = zxy_to_tile_id(z, x, y).unwrap();
+                    assert!(ids.contains(&tile_id));
+                    assert_eq!(tile_id_to_zxy(tile_id), Some((z, x, y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_max_zoom() {
+        let max = (1u32 << MAX_ZOOM) - 1;
+        for (x, y) in [
+            (0, 0),
+            (max, 0),
+            (0, max),
+            (max, max),
+            (123456789, 987654321),
+        ] {
+            let tile_id = zxy_to_tile_id(MAX_ZOOM, x, y).unwrap();
+            assert_eq!(tile_id_to_zxy(tile_id), Some((MAX_ZOOM, x, y)));
+        }
+    }
+
+    #[test]
+    fn test_tile_id_range() {
+        assert_eq!(tile_id_range(0, 0), 0..1);
+        assert_eq!(tile_id_range(1, 2), 1..21);
+        assert_eq!(tile_id_range(3, 2), 21..21);
+        assert_eq!(
+            tile_id_range(0, MAX_ZOOM),
+            0..zoom_offset(MAX_ZOOM) + (1 << 62)
+        );
+        assert_eq!(tile_id_range(0, u8::MAX), tile_id_range(0, MAX_ZOOM));
+    }
+
+    #[test]
+    fn test_zxy_to_tile_id_out_of_range() {
+        assert_eq!(zxy_to_tile_id(0, 1, 0), None);
+        assert_eq!(zxy_to_tile_id(3, 0, 8), None);
+        assert_eq!(zxy_to_tile_id(32, 0, 0), None);
+    }
+}
@@ -0,0 +1,482 @@
+use std::ops::Range;
+
+use crate::{
+    Error, PMTilesFile, RangeReader,
+    bbox::BBox,
+    directory::{MAX_DEPTH, PMTilesEntry},
+    tile_id,
+};
+
+/// An entry visited by [`Traverse`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryItem {
+    /// 0 for the entries of the root directory, 1 for those of the leaf directories it points
+    /// to, and so on.
+    pub depth: usize,
+    pub entry: PMTilesEntry,
+}
+
+/// Depth-first traversal over the entries of the root directory and all the leaf directories,
+/// created by [`PMTilesFile::traverse`].
+///
+/// An entry pointing to a leaf directory is yielded before the entries of the directory. The
+/// directory is read lazily, so it is never read if [`Traverse::skip_children`] is called.
+pub struct Traverse<'a, R> {
+    file: &'a mut PMTilesFile<R>,
+    /// The directories being visited, innermost last
+    stack: Vec<Directory>,
+    /// The leaf directory yielded last and the end of its tile IDs, which is read on the next
+    /// call
+    pending: Option<(PMTilesEntry, u64)>,
+    tile_ids: Range<u64>,
+}
+
+struct Directory {
+    /// The remaining entries
+    entries: std::vec::IntoIter<PMTilesEntry>,
+    /// The tile IDs of the entries are below this
+    end: u64,
+}
+
+impl<'a, R: RangeReader> Traverse<'a, R> {
+    pub(crate) fn new(file: &'a mut PMTilesFile<R>) -> Result<Self, Error> {
+        let root = file.parse_root_directory()?;
+        Ok(Self {
+            file,
+            stack: vec![Directory {
+                entries: root.into_iter(),
+                end: u64::MAX,
+            }],
+            pending: None,
+            tile_ids: 0..u64::MAX,
+        })
+    }
+
+    /// Only visits the tiles whose IDs are in the range, and the leaf directories that may
+    /// contain such tiles. A leaf directory covers the tile IDs from that of its entry up to
+    /// that of the next entry, so other directories are skipped without being read.
+    pub fn with_tile_ids(mut self, tile_ids: Range<u64>) -> Self {
+        self.tile_ids = tile_ids;
+        self
+    }
+
+    /// Doesn't descend into the leaf directory yielded last.
+    pub fn skip_children(&mut self) {
+        self.pending = None;
+    }
+
+    /// Skips the rest of the directory the entry yielded last belongs to, including the
+    /// children of the entry.
+    pub fn skip_siblings(&mut self) {
+        self.pending = None;
+        self.stack.pop();
+    }
+
+    fn read_pending(&mut self) -> Result<(), Error> {
+        let Some((entry, end)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        if self.stack.len() > MAX_DEPTH {
+            return Err(Error::CorruptDirectory {
+                offset: self.file.leaf_directory_offset(entry.offset)?,
+            });
+        }
+
+        let entries = self
+            .file
+            .parse_leaf_directory(entry.offset, entry.length as usize)?;
+        self.stack.push(Directory {
+            entries: entries.into_iter(),
+            end,
+        });
+        Ok(())
+    }
+}
+
+impl<R: RangeReader> Iterator for Traverse<'_, R> {
+    type Item = Result<DirectoryItem, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.read_pending() {
+            // stop after the error
+            self.stack.clear();
+            return Some(Err(e));
+        }
+
+        while let Some(dir) = self.stack.last_mut() {
+            let Some(entry) = dir.entries.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            if entry.tile_id >= self.tile_ids.end {
+                // entries are sorted by tile ID, so the rest is out of range too
+                self.stack.clear();
+                return None;
+            }
+
+            let end = match dir.entries.as_slice().first() {
+                Some(next) => next.tile_id,
+                None => dir.end,
+            };
+            if entry.is_tile {
+                if entry.tile_id < self.tile_ids.start {
+                    continue;
+                }
+            } else {
+                if end <= self.tile_ids.start {
+                    continue;
+                }
+                self.pending = Some((entry.clone(), end));
+            }
+
+            return Some(Ok(DirectoryItem {
+                depth: self.stack.len() - 1,
+                entry,
+            }));
+        }
+
+        None
+    }
+}
+
+/// Options for [`PMTilesFile::tiles`].
+#[derive(Debug, Clone)]
+pub struct TilesOptions {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// Yield each tile of a run of tiles sharing the same data, instead of the data once for
+    /// the whole run.
+    pub expand_run_length: bool,
+    /// Only yield the tiles intersecting the box. The other tiles are skipped before their data
+    /// is read.
+    pub bbox: Option<BBox>,
+}
+
+impl Default for TilesOptions {
+    fn default() -> Self {
+        Self {
+            min_zoom: 0,
+            max_zoom: tile_id::MAX_ZOOM,
+            expand_run_length: true,
+            bbox: None,
+        }
+    }
+}
+
+/// A tile yielded by [`Tiles`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileData {
+    pub tile_id: u64,
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+    /// The number of tiles from `tile_id` sharing the data. Always 1 if run lengths are
+    /// expanded.
+    pub run_length: u64,
+    /// The decompressed tile data. It's always copied, even from a memory-mapped archive; use
+    /// [`PMTilesFile::parse_tile`] with [`PMTilesFile::tile_entries`] to avoid the copy.
+    pub data: Vec<u8>,
+}
+
+/// Lazy iterator over the tiles of the archive in tile ID order, created by
+/// [`PMTilesFile::tiles`]. Tile data is read only when the tile is yielded, and leaf directories
+/// only when they may contain tiles in the zoom range.
+pub struct Tiles<'a, R> {
+    traverse: Traverse<'a, R>,
+    expand_run_length: bool,
+    bbox: Option<BBox>,
+    /// The entry read ahead while looking for the end of a run
+    peeked: Option<Result<PMTilesEntry, Error>>,
+}
+
+impl<R: RangeReader> Tiles<'_, R> {
+    /// Returns the next tile entry in the bbox.
+    fn next_entry(&mut self) -> Option<Result<PMTilesEntry, Error>> {
+        if let Some(entry) = self.peeked.take() {
+            return Some(entry);
+        }
+
+        let bbox = self.bbox;
+        self.traverse.by_ref().find_map(|item| match item {
+            Ok(DirectoryItem { entry, .. }) if entry.is_tile => bbox
+                .is_none_or(|b| b.intersects_tile_id(entry.tile_id))
+                .then_some(Ok(entry)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+impl<R: RangeReader> Iterator for Tiles<'_, R> {
+    type Item = Result<TileData, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = match self.next_entry()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+
+        // parse_directory expands run lengths, so gather the run back
+        let mut run_length = 1;
+        if !self.expand_run_length {
+            while let Some(next) = self.next_entry() {
+                if let Ok(e) = &next
+                    && (e.offset, e.length) == (entry.offset, entry.length)
+                    && e.tile_id == entry.tile_id + run_length
+                {
+                    run_length += 1;
+                    continue;
+                }
+                self.peeked = Some(next);
+                break;
+            }
+        }
+
+        let data = match self
+            .traverse
+            .file
+            .read_tile_data(entry.offset, entry.length as usize)
+        {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e)),
+        };
+
+        // the tile IDs are limited to the zoom range, which is up to MAX_ZOOM
+        let (z, x, y) = tile_id::tile_id_to_zxy(entry.tile_id).unwrap();
+
+        Some(Ok(TileData {
+            tile_id: entry.tile_id,
+            z,
+            x,
+            y,
+            run_length,
+            data,
+        }))
+    }
+}
+
+impl<R: RangeReader> PMTilesFile<R> {
+    /// Visits the entries of all the directories. See [`Traverse`].
+    pub fn traverse(&mut self) -> Result<Traverse<'_, R>, Error> {
+        Traverse::new(self)
+    }
+
+    /// Visits the entries of all the tiles, at any depth of leaf directories.
+    pub fn tile_entries(
+        &mut self,
+    ) -> Result<impl Iterator<Item = Result<PMTilesEntry, Error>> + '_, Error> {
+        Ok(self.traverse()?.filter_map(|item| match item {
+            Ok(DirectoryItem { entry, .. }) if entry.is_tile => Some(Ok(entry)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    /// Visits the tiles and reads their data lazily. See [`Tiles`].
+    pub fn tiles(&mut self, options: &TilesOptions) -> Result<Tiles<'_, R>, Error> {
+        let tile_ids = tile_id::tile_id_range(options.min_zoom, options.max_zoom);
+        Ok(Tiles {
+            traverse: self.traverse()?.with_tile_ids(tile_ids),
+            expand_run_length: options.expand_run_length,
+            bbox: options.bbox,
+            peeked: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_util::{build_archive, make_tile};
+
+    // 9 tiles at z2 with 2 entries per directory: 5 leaf directories of tiles, 3 pointing to
+    // them, and 2 pointing to those from the root
+    fn nested_archive() -> Vec<u8> {
+        let tiles: Vec<_> = (0..9)
+            .map(|i| {
+                let label = format!("{i}");
+                (
+                    (2, i % 4, i / 4),
+                    make_tile(&[("Anno", &[&[("vt_text", &label)]])]),
+                )
+            })
+            .collect();
+        build_archive(&tiles, 2)
+    }
+
+    #[test]
+    fn test_traverse_nested() {
+        let data = nested_archive();
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        let items: Vec<DirectoryItem> = file.traverse().unwrap().collect::<Result<_, _>>().unwrap();
+        let tiles: Vec<&DirectoryItem> = items.iter().filter(|i| i.entry.is_tile).collect();
+        assert_eq!(tiles.len(), 9);
+        assert!(tiles.iter().all(|i| i.depth == 3));
+        assert!(
+            tiles
+                .windows(2)
+                .all(|w| w[0].entry.tile_id < w[1].entry.tile_id)
+        );
+        assert_eq!(items.iter().filter(|i| i.depth == 0).count(), 2);
+
+        let tile_entries: Vec<PMTilesEntry> = file
+            .tile_entries()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tile_entries.len(), 9);
+
+        for e in &tile_entries {
+            let tile = file.parse_tile(e.offset, e.length as usize).unwrap();
+            assert_eq!(tile.layers[0].name, "Anno");
+        }
+    }
+
+    #[test]
+    fn test_traverse_skip() {
+        let data = nested_archive();
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        let mut traverse = file.traverse().unwrap();
+        let mut depths = vec![];
+        while let Some(item) = traverse.next() {
+            let item = item.unwrap();
+            depths.push(item.depth);
+            if item.depth == 1 {
+                traverse.skip_children();
+            }
+        }
+        assert_eq!(depths, vec![0, 1, 1, 0, 1]);
+
+        let mut traverse = file.traverse().unwrap();
+        let first = traverse.next().unwrap().unwrap();
+        assert_eq!(first.depth, 0);
+        traverse.skip_siblings();
+        assert!(traverse.next().is_none());
+    }
+
+    #[test]
+    fn test_traverse_tile_ids() {
+        // tile IDs 0 to 20 in leaf directories of 4 entries: 0-3, 4-7, ..., 16-19, 20
+        let tiles: Vec<_> = (0..21)
+            .map(|i| {
+                let label = format!("{i}");
+                let zxy = tile_id::tile_id_to_zxy(i).unwrap();
+                (zxy, make_tile(&[("Anno", &[&[("vt_text", &label)]])]))
+            })
+            .collect();
+        let data = build_archive(&tiles, 4);
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        // z2, i.e. 5 to 20
+        let items: Vec<DirectoryItem> = file
+            .traverse()
+            .unwrap()
+            .with_tile_ids(tile_id::tile_id_range(2, 2))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let leaves: Vec<u64> = items
+            .iter()
+            .filter(|i| i.depth == 1)
+            .map(|i| i.entry.tile_id)
+            .collect();
+        assert_eq!(leaves, vec![4, 8, 12, 16, 20]);
+        let tiles: Vec<u64> = items
+            .iter()
+            .filter(|i| i.entry.is_tile)
+            .map(|i| i.entry.tile_id)
+            .collect();
+        assert_eq!(tiles, (5..21).collect::<Vec<_>>());
+
+        // z1, i.e. 1 to 4, stops before reading the directories beyond
+        let leaves: Vec<(usize, u64)> = file
+            .traverse()
+            .unwrap()
+            .with_tile_ids(tile_id::tile_id_range(1, 1))
+            .map(|i| i.map(|i| (i.depth, i.entry.tile_id)))
+            .filter(|i| matches!(i, Ok((1, _))))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(leaves, vec![(1, 0), (1, 4)]);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let shared = make_tile(&[("Anno", &[&[("vt_text", "shared")]])]);
+        let mut tiles = vec![((0, 0, 0), make_tile(&[("Anno", &[&[("vt_text", "z0")]])]))];
+        // tile IDs 1 to 4, then 5 to 8 are shared
+        for (x, y) in [(0, 0), (0, 1), (1, 1), (1, 0)] {
+            tiles.push(((1, x, y), shared.clone()));
+        }
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (0, 1)] {
+            tiles.push(((2, x, y), shared.clone()));
+        }
+        tiles.push(((2, 3, 3), make_tile(&[("Anno", &[&[("vt_text", "z2")]])])));
+        let data = build_archive(&tiles, 2);
+        let mut file = PMTilesFile::from_reader(Cursor::new(data.as_slice())).unwrap();
+
+        let all: Vec<TileData> = file
+            .tiles(&TilesOptions::default())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(all.len(), 10);
+        assert!(all.iter().all(|t| t.run_length == 1));
+        assert_eq!((all[2].z, all[2].x, all[2].y), (1, 0, 1));
+        assert_eq!(crate::decode_tile(&all[1].data).unwrap(), shared);
+
+        let runs: Vec<TileData> = file
+            .tiles(&TilesOptions {
+                expand_run_length: false,
+                ..Default::default()
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let runs: Vec<(u64, u64)> = runs.iter().map(|t| (t.tile_id, t.run_length)).collect();
+        let last_id = tile_id::zxy_to_tile_id(2, 3, 3).unwrap();
+        assert_eq!(runs, vec![(0, 1), (1, 8), (last_id, 1)]);
+
+        let z1: Vec<(u64, u64)> = file
+            .tiles(&TilesOptions {
+                min_zoom: 1,
+                max_zoom: 1,
+                expand_run_length: false,
+                ..Default::default()
+            })
+            .unwrap()
+            .map(|t| t.map(|t| (t.tile_id, t.run_length)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(z1, vec![(1, 4)]);
+
+        // the data of 2/3/3 is the last in the archive, so cut it off to check that it's not read
+        let mut file = PMTilesFile::from_reader(Cursor::new(&data[..data.len() - 1])).unwrap();
+        let west: Vec<(u8, u32, u32)> = file
+            .tiles(&TilesOptions {
+                bbox: Some("-170,-80,-10,80".parse().unwrap()),
+                ..Default::default()
+            })
+            .unwrap()
+            .map(|t| t.map(|t| (t.z, t.x, t.y)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            west,
+            vec![
+                (0, 0, 0),
+                (1, 0, 0),
+                (1, 0, 1),
+                (2, 0, 0),
+                (2, 1, 0),
+                (2, 1, 1),
+                (2, 0, 1)
+            ]
+        );
+    }
+}
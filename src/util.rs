@@ -1,63 +1,96 @@
-use std::{
-    error::Error,
-    io::{Read as _, Seek as _},
-};
+use std::borrow::Cow;
+use std::io::Read as _;
 
 use flate2::read::GzDecoder;
-use nom::error::ErrorKind;
+use nom::{IResult, error::ErrorKind};
 use prost::Message as _;
 
 use crate::{
-    directory::{self, PMTilesEntry},
+    Error,
+    directory::{self, DirectoryCache, MAX_DEPTH, PMTilesEntry},
     header::{PMTilesCompression, PMTilesHeaderV3},
     mvt,
+    reader::{MmapReader, RangeReader},
 };
 
-pub(crate) fn nom_error<T>(input: &[u8]) -> Result<(&[u8], T), nom::Err<nom::error::Error<&[u8]>>> {
+pub(crate) fn nom_error<T>(input: &[u8]) -> IResult<&[u8], T> {
     Err(nom::Err::Error(nom::error::Error::new(
         input,
         ErrorKind::Fail,
     )))
 }
 
-pub struct PMTilesFile {
-    file: std::fs::File,
+/// A PMTiles archive opened for reading.
+///
+/// The archive is read from a local file by default, but any [`RangeReader`] can be used as
+/// the source via [`PMTilesFile::from_reader`].
+pub struct PMTilesFile<R = std::fs::File> {
+    file: R,
     header: PMTilesHeaderV3,
+    directory_cache: Option<DirectoryCache>,
 }
 
 impl PMTilesFile {
-    pub fn new<T: AsRef<std::path::Path>>(file: T) -> Result<Self, Box<dyn Error>> {
-        let mut file = std::fs::File::open(file.as_ref())?;
-        let mut header_data = vec![0u8; crate::header::HEADER_BYTES];
-        file.read_exact(&mut header_data)?;
-        let (rest, header) =
-            crate::header::parse_header(&header_data).expect("Failed to parse haeder");
+    /// Opens the archive and parses its header.
+    pub fn new<T: AsRef<std::path::Path>>(file: T) -> Result<Self, Error> {
+        let file =
+            std::fs::File::open(file.as_ref()).map_err(|source| Error::Io { offset: 0, source })?;
+        Self::from_reader(file)
+    }
+}
+
+impl PMTilesFile<MmapReader> {
+    /// Opens the archive as a memory-mapped file. See [`MmapReader::open`] for the caveat.
+    pub fn open_mmap<T: AsRef<std::path::Path>>(file: T) -> Result<Self, Error> {
+        let reader = MmapReader::open(file).map_err(|source| Error::Io { offset: 0, source })?;
+        Self::from_reader(reader)
+    }
+}
+
+impl<R: RangeReader> PMTilesFile<R> {
+    /// Parses the header of the archive read from `reader`.
+    pub fn from_reader(mut reader: R) -> Result<Self, Error> {
+        let header_data = reader
+            .read_range(0, crate::header::HEADER_BYTES)
+            .map_err(|source| Error::Io { offset: 0, source })?;
+        let header = crate::header::read_header(&header_data)?;
 
-        debug_assert!(rest.is_empty());
+        Ok(Self {
+            file: reader,
+            header,
+            directory_cache: None,
+        })
+    }
 
-        Ok(Self { file, header })
+    /// Keeps up to `capacity` parsed directories in memory so that they are read only once.
+    /// This is useful when reading is expensive, e.g. over HTTP.
+    pub fn with_directory_cache(mut self, capacity: usize) -> Self {
+        self.directory_cache = Some(DirectoryCache::new(capacity));
+        self
     }
 
     pub fn parse_header(&self) -> &PMTilesHeaderV3 {
         &self.header
     }
 
-    pub fn parse_metadata(&mut self) -> Result<String, Box<dyn Error>> {
+    pub fn parse_metadata(&mut self) -> Result<String, Error> {
+        let offset = self.header.metadata_offset;
         let metadata_decoded = decompress(
             &mut self.file,
-            self.header.metadata_offset,
+            offset,
             self.header.metadata_length as usize,
             &self.header.internal_compression,
         )?;
 
-        Ok(String::from_utf8(metadata_decoded)?)
+        String::from_utf8(metadata_decoded.into_owned())
+            .map_err(|source| Error::InvalidMetadata { offset, source })
     }
 
-    fn parse_directory(
-        &mut self,
-        offset: u64,
-        length: usize,
-    ) -> Result<Vec<PMTilesEntry>, Box<dyn Error>> {
+    fn parse_directory(&mut self, offset: u64, length: usize) -> Result<Vec<PMTilesEntry>, Error> {
+        if let Some(entries) = self.directory_cache.as_ref().and_then(|c| c.get(offset)) {
+            return Ok(entries.clone());
+        }
+
         let decoded = decompress(
             &mut self.file,
             offset,
@@ -65,14 +98,19 @@ impl PMTilesFile {
             &self.header.internal_compression,
         )?;
         let (rest, entries) =
-            directory::parse_directory(&decoded).expect("Failed to parse directory");
+            directory::parse_directory(&decoded).map_err(|_| Error::CorruptDirectory { offset })?;
+        if !rest.is_empty() {
+            return Err(Error::CorruptDirectory { offset });
+        }
 
-        debug_assert!(rest.is_empty());
+        if let Some(cache) = &mut self.directory_cache {
+            cache.insert(offset, entries.clone());
+        }
 
         Ok(entries)
     }
 
-    pub fn parse_root_directory(&mut self) -> Result<Vec<PMTilesEntry>, Box<dyn Error>> {
+    pub fn parse_root_directory(&mut self) -> Result<Vec<PMTilesEntry>, Error> {
         self.parse_directory(
             self.header.root_directory_offset,
             self.header.root_directory_length as usize,
@@ -83,66 +121,390 @@ impl PMTilesFile {
         &mut self,
         offset: u64,
         length: usize,
-    ) -> Result<Vec<PMTilesEntry>, Box<dyn Error>> {
-        self.parse_directory(self.header.leaf_directories_offset + offset, length)
+    ) -> Result<Vec<PMTilesEntry>, Error> {
+        self.parse_directory(self.leaf_directory_offset(offset)?, length)
+    }
+
+    /// Converts the offset of a leaf directory relative to the leaf directories section to the
+    /// offset in the archive. Offsets that overflow come from corrupt directories.
+    pub(crate) fn leaf_directory_offset(&self, offset: u64) -> Result<u64, Error> {
+        let base = self.header.leaf_directories_offset;
+        base.checked_add(offset)
+            .ok_or(Error::CorruptDirectory { offset: base })
+    }
+
+    /// Converts the offset of a tile relative to the tile data section to the offset in the
+    /// archive.
+    fn tile_offset(&self, offset: u64) -> Result<u64, Error> {
+        let base = self.header.tile_data_offset;
+        base.checked_add(offset).ok_or_else(|| Error::Io {
+            offset: base,
+            source: std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("tile offset {offset} is out of range"),
+            ),
+        })
+    }
+
+    /// Finds the entry of the tile with the given tile ID, descending into leaf directories as
+    /// needed. Returns `None` if the archive doesn't contain the tile.
+    pub fn find_tile_entry(&mut self, tile_id: u64) -> Result<Option<PMTilesEntry>, Error> {
+        let mut entries = self.parse_root_directory()?;
+        let mut depth = 0;
+
+        loop {
+            // entries are sorted by tile_id, so the candidate is the last one whose tile_id is
+            // not greater than the target
+            let i = match entries.binary_search_by_key(&tile_id, |e| e.tile_id) {
+                Ok(i) => i,
+                Err(0) => return Ok(None),
+                Err(i) => i - 1,
+            };
+            let entry = entries.swap_remove(i);
+
+            if entry.is_tile {
+                return Ok((entry.tile_id == tile_id).then_some(entry));
+            }
+
+            // e.g. a leaf directory pointing to itself
+            if depth == MAX_DEPTH {
+                return Err(Error::CorruptDirectory {
+                    offset: self.leaf_directory_offset(entry.offset)?,
+                });
+            }
+            depth += 1;
+            entries = self.parse_leaf_directory(entry.offset, entry.length as usize)?;
+        }
+    }
+
+    /// Reads the decompressed bytes of the tile. `offset` is relative to the tile data section.
+    pub fn read_tile_data(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, Error> {
+        Ok(self.read_tile_data_cow(offset, length)?.into_owned())
+    }
+
+    pub fn parse_tile(&mut self, offset: u64, length: usize) -> Result<mvt::Tile, Error> {
+        let tile_offset = self.tile_offset(offset)?;
+        let tile_decoded = self.read_tile_data_cow(offset, length)?;
+        decode_tile(&tile_decoded).map_err(|source| Error::Decode {
+            offset: tile_offset,
+            source,
+        })
     }
 
-    pub fn parse_tile(&mut self, offset: u64, length: usize) -> Result<mvt::Tile, Box<dyn Error>> {
-        let tile_decoded = decompress(
+    // Uncompressed tiles are borrowed from the reader if it supports it (e.g. MmapReader)
+    fn read_tile_data_cow(&mut self, offset: u64, length: usize) -> Result<Cow<'_, [u8]>, Error> {
+        let tile_offset = self.tile_offset(offset)?;
+        decompress(
             &mut self.file,
-            self.header.tile_data_offset + offset,
+            tile_offset,
             length,
             &self.header.tile_compression,
-        )?;
-        let tile = mvt::Tile::decode(tile_decoded.as_slice())?;
-
-        Ok(tile)
+        )
     }
 }
 
-pub(crate) fn decompress(
-    file: &mut std::fs::File,
+/// Decodes the decompressed bytes of a vector tile.
+pub fn decode_tile(data: &[u8]) -> Result<mvt::Tile, prost::DecodeError> {
+    mvt::Tile::decode(data)
+}
+
+pub(crate) fn decompress<'a, R: RangeReader>(
+    file: &'a mut R,
     offset: u64,
     length: usize,
     compression: &PMTilesCompression,
-) -> Result<Vec<u8>, Box<dyn Error>> {
+) -> Result<Cow<'a, [u8]>, Error> {
     // leaf dir might not exist
     if length == 0 {
-        return Ok(vec![]);
+        return Ok(Cow::Borrowed(&[]));
     }
 
-    let mut raw_bytes = vec![0u8; length];
-    file.seek(std::io::SeekFrom::Start(offset))?;
-    file.read_exact(&mut raw_bytes)?;
+    let raw_bytes = file
+        .read_range(offset, length)
+        .map_err(|source| Error::Io { offset, source })?;
 
     let mut decoded = vec![];
-    let mut decoder = match &compression {
-        PMTilesCompression::Unknown => unimplemented!(),
+    let result = match &compression {
+        PMTilesCompression::Unknown => return Err(Error::UnsupportedCompression { offset }),
         PMTilesCompression::None => return Ok(raw_bytes),
-        PMTilesCompression::Gzip => GzDecoder::new(raw_bytes.as_slice()),
-        PMTilesCompression::Brotli => unimplemented!(),
-        PMTilesCompression::Zstd => unimplemented!(),
+        PMTilesCompression::Gzip => GzDecoder::new(&*raw_bytes).read_to_end(&mut decoded),
+        PMTilesCompression::Brotli => {
+            // 4096 is the buffer size recommended in the brotli crate's docs
+            brotli::Decompressor::new(&*raw_bytes, 4096).read_to_end(&mut decoded)
+        }
+        PMTilesCompression::Zstd => zstd::Decoder::new(&*raw_bytes)
+            .and_then(|mut decoder| decoder.read_to_end(&mut decoded)),
     };
-    decoder.read_to_end(&mut decoded)?;
-    Ok(decoded)
+    result.map_err(|source| Error::Decompression { offset, source })?;
+
+    Ok(Cow::Owned(decoded))
 }
 
-pub(crate) fn print_tile_value(value: &mvt::tile::Value) {
-    if let Some(v) = value.bool_value {
-        print!("{v}, ");
-    } else if let Some(v) = value.double_value {
-        print!("{v}, ");
-    } else if let Some(v) = value.float_value {
-        print!("{v}, ");
-    } else if let Some(v) = value.int_value {
-        print!("{v}, ");
-    } else if let Some(v) = value.sint_value {
-        print!("{v}, ");
-    } else if let Some(v) = value.uint_value {
-        print!("{v}, ");
-    } else if let Some(v) = &value.string_value {
-        print!(r#""{v}", "#);
-    } else {
-        print!("(null)")
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        test_util::{build_archive, make_tile},
+        tile_id::zxy_to_tile_id,
+    };
+
+    const FIXTURE: &[u8] = include_bytes!("./test/test_fixture_1.pmtiles");
+
+    // The brotli and zstd fixtures are test_fixture_1.pmtiles with every section recompressed
+    fn assert_same_contents(data: &[u8], compression: PMTilesCompression) {
+        let mut expected = PMTilesFile::from_reader(Cursor::new(FIXTURE)).unwrap();
+        let mut file = PMTilesFile::from_reader(Cursor::new(data)).unwrap();
+
+        assert_eq!(file.parse_header().internal_compression, compression);
+        assert_eq!(file.parse_header().tile_compression, compression);
+
+        assert_eq!(
+            file.parse_metadata().unwrap(),
+            expected.parse_metadata().unwrap()
+        );
+
+        let entries = file.parse_root_directory().unwrap();
+        let expected_entries = expected.parse_root_directory().unwrap();
+        assert_eq!(entries.len(), expected_entries.len());
+        assert_eq!(entries[0].tile_id, expected_entries[0].tile_id);
+
+        let tile = file
+            .parse_tile(entries[0].offset, entries[0].length as usize)
+            .unwrap();
+        let expected_tile = expected
+            .parse_tile(
+                expected_entries[0].offset,
+                expected_entries[0].length as usize,
+            )
+            .unwrap();
+        assert_eq!(tile, expected_tile);
+    }
+
+    #[test]
+    fn test_read_mmap() {
+        let mut file = PMTilesFile::open_mmap("src/test/test_fixture_1.pmtiles").unwrap();
+        let mut expected = PMTilesFile::from_reader(Cursor::new(FIXTURE)).unwrap();
+
+        assert_eq!(file.parse_header(), expected.parse_header());
+        assert_eq!(
+            file.parse_metadata().unwrap(),
+            expected.parse_metadata().unwrap()
+        );
+        assert_eq!(
+            file.parse_tile(0, 69).unwrap(),
+            expected.parse_tile(0, 69).unwrap()
+        );
+
+        // out of bounds
+        assert!(matches!(
+            file.parse_tile(0, 1000),
+            Err(Error::Io { offset: 399, .. })
+        ));
+    }
+
+    #[test]
+    fn test_find_tile_entry() {
+        let mut file = PMTilesFile::new("src/test/test_fixture_1.pmtiles").unwrap();
+
+        let entry = file.find_tile_entry(0).unwrap().unwrap();
+        assert_eq!(entry.offset, 0);
+        assert_eq!(entry.length, 69);
+
+        assert!(file.find_tile_entry(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_tile_entry_in_leaves() {
+        let tiles: Vec<_> = (0..4)
+            .flat_map(|x| (0..4).map(move |y| (x, y)))
+            .map(|(x, y)| {
+                let name = format!("{x}/{y}");
+                ((2, x, y), make_tile(&[(&name, &[&[("name", &name)]])]))
+            })
+            .collect();
+        // 16 tiles in directories of 2 entries, nested 3 levels deep
+        let data = build_archive(&tiles, 2);
+        let mut file = PMTilesFile::from_reader(Cursor::new(data)).unwrap();
+
+        for ((z, x, y), tile) in &tiles {
+            let tile_id = zxy_to_tile_id(*z, *x, *y).unwrap();
+            let entry = file.find_tile_entry(tile_id).unwrap().unwrap();
+            assert!(entry.is_tile);
+            assert_eq!(entry.tile_id, tile_id);
+            assert_eq!(
+                &file
+                    .parse_tile(entry.offset, entry.length as usize)
+                    .unwrap(),
+                tile
+            );
+        }
+
+        // before, between and after the tiles
+        assert!(file.find_tile_entry(0).unwrap().is_none());
+        assert!(file.find_tile_entry(100).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_tile_entry_in_cyclic_leaves() {
+        // the root directory points to a leaf directory, which points to itself
+        let leaf = [1, 0, 0, 2, 1];
+        let root = [1, 0, 0, 5, 1];
+        let mut data = build_archive(&[], 1);
+        data.truncate(127);
+        let patch = |data: &mut Vec<u8>, at: usize, v: u64| {
+            data[at..at + 8].copy_from_slice(&v.to_le_bytes());
+        };
+        patch(&mut data, 8, 127); // root directory
+        patch(&mut data, 16, root.len() as u64);
+        patch(&mut data, 24, 132); // metadata
+        patch(&mut data, 32, 0);
+        patch(&mut data, 40, 132); // leaf directories
+        patch(&mut data, 48, leaf.len() as u64);
+        patch(&mut data, 56, 137); // tile data
+        patch(&mut data, 64, 0);
+        data.extend(root);
+        data.extend(leaf);
+
+        let mut file = PMTilesFile::from_reader(Cursor::new(data)).unwrap();
+        assert!(matches!(
+            file.find_tile_entry(0),
+            Err(Error::CorruptDirectory { offset: 132 })
+        ));
+    }
+
+    #[test]
+    fn test_read_from_memory() {
+        let mut file = PMTilesFile::from_reader(Cursor::new(FIXTURE)).unwrap();
+        assert_eq!(file.parse_header().root_directory_offset, 127);
+
+        let entries = file.parse_root_directory().unwrap();
+        let tile = file
+            .parse_tile(entries[0].offset, entries[0].length as usize)
+            .unwrap();
+        assert_eq!(tile.layers[0].name, "test_fixture_1pmtiles");
+    }
+
+    fn open_corrupted(
+        corrupt: impl FnOnce(&mut Vec<u8>),
+    ) -> Result<PMTilesFile<Cursor<Vec<u8>>>, Error> {
+        let mut data = FIXTURE.to_vec();
+        corrupt(&mut data);
+        PMTilesFile::from_reader(Cursor::new(data))
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let result = open_corrupted(|data| data[..7].copy_from_slice(b"MBTiles"));
+        assert!(matches!(result, Err(Error::BadMagic)));
+
+        let result = open_corrupted(|data| data[7] = 4);
+        assert!(matches!(result, Err(Error::UnsupportedVersion(4))));
+
+        // internal_compression
+        let result = open_corrupted(|data| data[97] = 5);
+        assert!(matches!(
+            result,
+            Err(Error::InvalidHeaderValue {
+                offset: 97,
+                value: 5
+            })
+        ));
+
+        let result = open_corrupted(|data| data.truncate(50));
+        assert!(matches!(result, Err(Error::Io { offset: 0, .. })));
+    }
+
+    #[test]
+    fn test_corrupted_sections() {
+        // the root directory is cut off
+        let mut file = open_corrupted(|data| data.truncate(140)).unwrap();
+        assert!(matches!(
+            file.parse_root_directory(),
+            Err(Error::Io { offset: 127, .. })
+        ));
+
+        // broken gzip magic number of the root directory
+        let mut file = open_corrupted(|data| data[127] = 0).unwrap();
+        assert!(matches!(
+            file.parse_root_directory(),
+            Err(Error::Decompression { offset: 127, .. })
+        ));
+
+        // the gzip-compressed directory is read as if it were uncompressed
+        let mut file = open_corrupted(|data| data[97] = 1).unwrap();
+        assert!(matches!(
+            file.parse_root_directory(),
+            Err(Error::CorruptDirectory { offset: 127 })
+        ));
+
+        // lengths far beyond the end of the archive
+        let huge = (1u64 << 40).to_le_bytes();
+        let mut file = open_corrupted(|data| data[16..24].copy_from_slice(&huge)).unwrap();
+        assert!(matches!(
+            file.parse_root_directory(),
+            Err(Error::Io { offset: 127, .. })
+        ));
+        let mut file = open_corrupted(|data| data[32..40].copy_from_slice(&huge)).unwrap();
+        assert!(matches!(
+            file.parse_metadata(),
+            Err(Error::Io { offset: 152, .. })
+        ));
+
+        let mut file = open_corrupted(|data| data[97] = 0).unwrap();
+        assert!(matches!(
+            file.parse_metadata(),
+            Err(Error::UnsupportedCompression { offset: 152 })
+        ));
+
+        // the gzip-compressed tile is read as if it were uncompressed
+        let mut file = open_corrupted(|data| data[98] = 1).unwrap();
+        assert!(matches!(
+            file.parse_tile(0, 69),
+            Err(Error::Decode { offset: 399, .. })
+        ));
+
+        // offsets from a corrupt directory that overflow
+        let mut file =
+            open_corrupted(|data| data[40..48].copy_from_slice(&1000u64.to_le_bytes())).unwrap();
+        assert!(matches!(
+            file.parse_tile(u64::MAX, 69),
+            Err(Error::Io { offset: 399, .. })
+        ));
+        assert!(matches!(
+            file.parse_leaf_directory(u64::MAX, 10),
+            Err(Error::CorruptDirectory { offset: 1000 })
+        ));
+    }
+
+    #[test]
+    fn test_trailing_bytes_in_directory() {
+        let mut data = build_archive(&[((0, 0, 0), make_tile(&[]))], 2);
+        // the root directory also covers the first byte of the metadata
+        let root_length = u64::from_le_bytes(data[16..24].try_into().unwrap());
+        data[16..24].copy_from_slice(&(root_length + 1).to_le_bytes());
+
+        let mut file = PMTilesFile::from_reader(Cursor::new(data)).unwrap();
+        assert!(matches!(
+            file.parse_root_directory(),
+            Err(Error::CorruptDirectory { offset: 127 })
+        ));
+    }
+
+    #[test]
+    fn test_decompress_brotli() {
+        assert_same_contents(
+            include_bytes!("./test/test_fixture_brotli.pmtiles"),
+            PMTilesCompression::Brotli,
+        );
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        assert_same_contents(
+            include_bytes!("./test/test_fixture_zstd.pmtiles"),
+            PMTilesCompression::Zstd,
+        );
     }
 }
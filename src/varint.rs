@@ -1,11 +1,17 @@
 use nom::{IResult, bytes::complete::take, bytes::complete::take_while};
 
+use crate::util;
+
 const MSB_MASK: u8 = 0b10000000;
 
 // cf. https://protobuf.dev/programming-guides/encoding/#varints
 
 pub(crate) fn parse_varint(input: &[u8]) -> IResult<&[u8], u64> {
     let (input, cont_bytes) = take_while(|x| x & MSB_MASK != 0)(input)?;
+    // a u64 takes at most 10 bytes
+    if cont_bytes.len() > 9 {
+        return util::nom_error(input);
+    }
     let (input, last_byte) = take(1usize)(input)?;
 
     let mut i = cont_bytes.len();
@@ -39,5 +45,8 @@ mod tests {
         let res = parse_varint(&b3).unwrap();
         assert!(res.0.is_empty());
         assert_eq!(res.1, 434398);
+
+        let b4 = [0xff; 11];
+        assert!(parse_varint(&b4).is_err());
     }
 }